@@ -4,7 +4,7 @@ extern crate test;
 extern crate rustc_serialize;
 
 use std::string;
-use rustc_serialize::json::{Json, Parser};
+use rustc_serialize::json::{ByteParser, Json, Parser};
 use test::Bencher;
 
 #[bench]
@@ -28,6 +28,34 @@ fn bench_streaming_small(b: &mut Bencher) {
         }
     });
 }
+
+fn many_small() -> Vec<string::String> {
+    (0..100).map(|i| format!(r#"{{"id": {}, "ok": true, "tags": ["a", "b"]}}"#, i)).collect()
+}
+
+#[bench]
+fn bench_streaming_many_small_fresh(b: &mut Bencher) {
+    let msgs = many_small();
+    b.iter( || {
+        for msg in msgs.iter() {
+            let mut parser = Parser::new(msg.chars());
+            while let Some(_) = parser.next() {}
+        }
+    });
+}
+
+#[bench]
+fn bench_streaming_many_small_reset(b: &mut Bencher) {
+    let msgs = many_small();
+    let mut parser = Parser::new("".chars());
+    b.iter( || {
+        for msg in msgs.iter() {
+            parser.reset(msg.chars());
+            while let Some(_) = parser.next() {}
+        }
+    });
+}
+
 #[bench]
 fn bench_small(b: &mut Bencher) {
     b.iter( || {
@@ -77,6 +105,53 @@ fn bench_streaming_large(b: &mut Bencher) {
         }
     });
 }
+
+fn records(text: &str) -> string::String {
+    let mut src = "[\n".to_string();
+    for i in 0..500 {
+        src.push_str(&format!(r#"{{"a": true, "b": null, "c": {}.1415, "d": "{}", "e": [1, 2, 3]}},"#,
+                              i, text));
+    }
+    src.push_str("{}]");
+    src
+}
+
+#[bench]
+fn bench_streaming_records_chars(b: &mut Bencher) {
+    let src = records("Hello world");
+    b.iter( || {
+        let mut parser = Parser::new(src.chars());
+        while let Some(_) = parser.next() {}
+    });
+}
+
+#[bench]
+fn bench_streaming_records_bytes(b: &mut Bencher) {
+    let src = records("Hello world");
+    b.iter( || {
+        let mut parser = ByteParser::new(src.bytes());
+        while let Some(_) = parser.next() {}
+    });
+}
+
+#[bench]
+fn bench_streaming_records_non_ascii_chars(b: &mut Bencher) {
+    let src = records("Gr\u{fc}\u{df}e, \u{4e16}\u{754c} \u{1f30d}");
+    b.iter( || {
+        let mut parser = Parser::new(src.chars());
+        while let Some(_) = parser.next() {}
+    });
+}
+
+#[bench]
+fn bench_streaming_records_non_ascii_bytes(b: &mut Bencher) {
+    let src = records("Gr\u{fc}\u{df}e, \u{4e16}\u{754c} \u{1f30d}");
+    b.iter( || {
+        let mut parser = ByteParser::new(src.bytes());
+        while let Some(_) = parser.next() {}
+    });
+}
+
 #[bench]
 fn bench_large(b: &mut Bencher) {
     let src = big_json();
@@ -15,8 +15,11 @@
 pub use self::FromBase64Error::*;
 pub use self::CharacterSet::*;
 
+use std::cmp;
 use std::fmt;
 use std::error;
+use std::str;
+use std::io::{self, Read, Write};
 
 /// Available encoding character sets
 #[derive(Clone, Copy, Debug)]
@@ -24,7 +27,79 @@ pub enum CharacterSet {
     /// The standard character set (uses `+` and `/`)
     Standard,
     /// The URL safe character set (uses `-` and `_`)
-    UrlSafe
+    UrlSafe,
+}
+
+/// The 64 characters of a character set, in order of the values they
+/// encode. This allows the use of non-standard character sets, with
+/// `to_base64_with`, `from_base64_with` and the streaming `Encoder` and
+/// `Decoder`.
+#[derive(Clone, Copy)]
+pub struct Alphabet {
+    chars: [u8; 64],
+}
+
+impl Alphabet {
+    /// Creates an alphabet from its 64 characters, which must be distinct
+    /// printable ASCII characters other than `=`. Returns None otherwise.
+    pub fn new(chars: &[u8]) -> Option<Alphabet> {
+        if chars.len() != 64 {
+            return None;
+        }
+        let mut seen = [false; 128];
+        for &c in chars {
+            if !(b'!' <= c && c <= b'~') || c == b'=' || seen[c as usize] {
+                return None;
+            }
+            seen[c as usize] = true;
+        }
+        let mut alphabet = Alphabet { chars: [0; 64] };
+        alphabet.chars.copy_from_slice(chars);
+        Some(alphabet)
+    }
+
+    /// Builds a table like `DECODE_TABLE` which accepts only the characters
+    /// of this alphabet.
+    fn decode_table(&self) -> [u8; 256] {
+        let mut table = [INVALID_CODE; 256];
+        for (i, &c) in self.chars.iter().enumerate() {
+            table[c as usize] = i as u8;
+        }
+        table[b'=' as usize] = EQUALS_CODE;
+        table[b'\r' as usize] = NEWLINE_CODE;
+        table[b'\n' as usize] = NEWLINE_CODE;
+        table
+    }
+}
+
+impl From<CharacterSet> for Alphabet {
+    fn from(char_set: CharacterSet) -> Alphabet {
+        let mut alphabet = Alphabet { chars: [0; 64] };
+        alphabet.chars.copy_from_slice(char_set.chars());
+        alphabet
+    }
+}
+
+impl fmt::Debug for Alphabet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The characters are all ASCII
+        write!(f, "Alphabet({:?})", str::from_utf8(&self.chars).unwrap())
+    }
+}
+
+impl CharacterSet {
+    fn chars(&self) -> &'static [u8] {
+        match *self {
+            Standard => STANDARD_CHARS,
+            UrlSafe => URLSAFE_CHARS,
+        }
+    }
+
+    /// Builds a table like `DECODE_TABLE` which accepts only the characters
+    /// of this character set.
+    fn decode_table(&self) -> [u8; 256] {
+        Alphabet::from(*self).decode_table()
+    }
 }
 
 /// Available newline types
@@ -61,6 +136,11 @@ pub static URL_SAFE: Config =
 pub static MIME: Config =
     Config {char_set: Standard, newline: Newline::CRLF, pad: true, line_length: Some(76)};
 
+/// Configuration for RFC 7468 PEM base64 encoding, which is also the output
+/// format of `openssl base64`
+pub static PEM: Config =
+    Config {char_set: Standard, newline: Newline::LF, pad: true, line_length: Some(64)};
+
 static STANDARD_CHARS: &'static[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                                         abcdefghijklmnopqrstuvwxyz\
                                         0123456789+/";
@@ -74,6 +154,22 @@ pub trait ToBase64 {
     /// Converts the value of `self` to a base64 value following the specified
     /// format configuration, returning the owned string.
     fn to_base64(&self, config: Config) -> String;
+
+    /// Converts the value of `self` to unpadded base64url, as used for the
+    /// segments of a JSON Web Token. This is the same as
+    /// `to_base64(URL_SAFE)`.
+    fn to_base64_url_nopad(&self) -> String {
+        self.to_base64(URL_SAFE)
+    }
+
+    /// Like `to_base64`, but encodes with the characters of `alphabet`
+    /// instead of `config.char_set`. This is available for values which can
+    /// be viewed as bytes, such as `[u8]`.
+    fn to_base64_with(&self, alphabet: &Alphabet, config: Config) -> String
+        where Self: AsRef<[u8]>
+    {
+        encode(self.as_ref(), &alphabet.chars, config)
+    }
 }
 
 impl ToBase64 for [u8] {
@@ -91,104 +187,126 @@ impl ToBase64 for [u8] {
     /// }
     /// ```
     fn to_base64(&self, config: Config) -> String {
-        let bytes = match config.char_set {
-            Standard => STANDARD_CHARS,
-            UrlSafe => URLSAFE_CHARS
-        };
-
-        let len = self.len();
-        let newline = match config.newline {
-            Newline::LF => "\n",
-            Newline::CRLF => "\r\n",
-        };
+        encode(self, config.char_set.chars(), config)
+    }
+}
 
-        // Preallocate memory.
-        let mut prealloc_len = (len + 2) / 3 * 4;
-        if let Some(line_length) = config.line_length {
-            let num_lines = match prealloc_len {
-                0 => 0,
-                n => (n - 1) / line_length
-            };
-            prealloc_len += num_lines * newline.bytes().count();
-        }
+/// Encodes `input` in the format given by `config`, using the character set
+/// `bytes` instead of `config.char_set`.
+fn encode(input: &[u8], bytes: &[u8], config: Config) -> String {
+    let len = input.len();
+    let newline = match config.newline {
+        Newline::LF => "\n",
+        Newline::CRLF => "\r\n",
+    };
 
-        let mut out_bytes = vec![b'='; prealloc_len];
+    // Preallocate memory, including any padding, which is dropped below.
+    let mut out_bytes = vec![b'='; encoded_len(len, Config {pad: true, ..config})];
 
-        // Deal with padding bytes
-        let mod_len = len % 3;
+    // Deal with padding bytes
+    let mod_len = len % 3;
 
-        // Use iterators to reduce branching
-        {
-            let mut cur_length = 0;
+    // Use iterators to reduce branching
+    {
+        let mut cur_length = 0;
 
-            let mut s_in = self[..len - mod_len].iter().map(|&x| x as u32);
-            let mut s_out = out_bytes.iter_mut();
+        let mut s_in = input[..len - mod_len].iter().map(|&x| x as u32);
+        let mut s_out = out_bytes.iter_mut();
 
-            // Convenient shorthand
-            let enc = |val| bytes[val as usize];
-            let mut write = |val| *s_out.next().unwrap() = val;
+        // Convenient shorthand
+        let enc = |val| bytes[val as usize];
+        let mut write = |val| *s_out.next().unwrap() = val;
 
-            // Iterate though blocks of 4
-            while let (Some(first), Some(second), Some(third)) =
-                        (s_in.next(), s_in.next(), s_in.next()) {
+        // Iterate though blocks of 4
+        while let (Some(first), Some(second), Some(third)) =
+                    (s_in.next(), s_in.next(), s_in.next()) {
 
-                // Line break if needed
-                if let Some(line_length) = config.line_length {
-                    if cur_length >= line_length {
-                        for b in newline.bytes() { write(b) };
-                        cur_length = 0;
-                    }
+            // Line break if needed
+            if let Some(line_length) = config.line_length {
+                if cur_length >= line_length {
+                    for b in newline.bytes() { write(b) };
+                    cur_length = 0;
                 }
+            }
 
-                let n = first << 16 | second << 8 | third;
-
-                // This 24-bit number gets separated into four 6-bit numbers.
-                write(enc((n >> 18) & 63));
-                write(enc((n >> 12) & 63));
-                write(enc((n >> 6 ) & 63));
-                write(enc((n >> 0 ) & 63));
+            let n = first << 16 | second << 8 | third;
 
-                cur_length += 4;
-            }
+            // This 24-bit number gets separated into four 6-bit numbers.
+            write(enc((n >> 18) & 63));
+            write(enc((n >> 12) & 63));
+            write(enc((n >> 6 ) & 63));
+            write(enc((n >> 0 ) & 63));
 
-            // Line break only needed if padding is required
-            if mod_len != 0 {
-                if let Some(line_length) = config.line_length {
-                    if cur_length >= line_length {
-                        for b in newline.bytes() { write(b) };
-                    }
-                }
-            }
+            cur_length += 4;
+        }
 
-            // Heh, would be cool if we knew this was exhaustive
-            // (the dream of bounded integer types)
-            match mod_len {
-                0 => (),
-                1 => {
-                    let n = (self[len-1] as u32) << 16;
-                    write(enc((n >> 18) & 63));
-                    write(enc((n >> 12) & 63));
+        // Line break only needed if padding is required
+        if mod_len != 0 {
+            if let Some(line_length) = config.line_length {
+                if cur_length >= line_length {
+                    for b in newline.bytes() { write(b) };
                 }
-                2 => {
-                    let n = (self[len-2] as u32) << 16 |
-                            (self[len-1] as u32) << 8;
-                    write(enc((n >> 18) & 63));
-                    write(enc((n >> 12) & 63));
-                    write(enc((n >> 6 ) & 63));
-                }
-                _ => panic!("Algebra is broken, please alert the math police")
             }
         }
 
-        // We get padding for "free", so only have to drop it if unwanted.
-        if !config.pad {
-            while let Some(&b'=') = out_bytes.last() {
-                out_bytes.pop();
+        // Heh, would be cool if we knew this was exhaustive
+        // (the dream of bounded integer types)
+        match mod_len {
+            0 => (),
+            1 => {
+                let n = (input[len-1] as u32) << 16;
+                write(enc((n >> 18) & 63));
+                write(enc((n >> 12) & 63));
             }
+            2 => {
+                let n = (input[len-2] as u32) << 16 |
+                        (input[len-1] as u32) << 8;
+                write(enc((n >> 18) & 63));
+                write(enc((n >> 12) & 63));
+                write(enc((n >> 6 ) & 63));
+            }
+            _ => panic!("Algebra is broken, please alert the math police")
         }
+    }
 
-        unsafe { String::from_utf8_unchecked(out_bytes) }
+    // We get padding for "free", so only have to drop it if unwanted.
+    if !config.pad {
+        while let Some(&b'=') = out_bytes.last() {
+            out_bytes.pop();
+        }
     }
+
+    unsafe { String::from_utf8_unchecked(out_bytes) }
+}
+
+/// Returns the length of the base64 encoding of `input_len` bytes in the
+/// format given by `config`, including padding and line breaks.
+pub fn encoded_len(input_len: usize, config: Config) -> usize {
+    let groups = (input_len + 2) / 3;
+    let chars = if config.pad {
+        groups * 4
+    } else {
+        input_len / 3 * 4 + [0, 2, 3][input_len % 3]
+    };
+    let newline_len = match config.newline {
+        Newline::LF => 1,
+        Newline::CRLF => 2,
+    };
+    // A line is broken before each group which would start at or after
+    // `line_length` characters, so lines hold whole groups.
+    let line_breaks = match config.line_length {
+        None => 0,
+        Some(_) if groups == 0 => 0,
+        Some(0) => groups,
+        Some(line_length) => (groups - 1) / ((line_length + 3) / 4),
+    };
+    chars + line_breaks * newline_len
+}
+
+/// Returns an upper bound on the number of bytes decoded from `input_len`
+/// bytes of base64. It is exact for padded input without line breaks.
+pub fn decoded_len_estimate(input_len: usize) -> usize {
+    (input_len + 3) / 4 * 3
 }
 
 impl<'a, T: ?Sized + ToBase64> ToBase64 for &'a T {
@@ -202,6 +320,82 @@ pub trait FromBase64 {
     /// Converts the value of `self`, interpreted as base64 encoded data, into
     /// an owned vector of bytes, returning the vector.
     fn from_base64(&self) -> Result<Vec<u8>, FromBase64Error>;
+
+    /// Converts the value of `self`, interpreted as base64url encoded data
+    /// such as a JSON Web Token segment, into an owned vector of bytes.
+    /// Padding is accepted but not required.
+    fn from_base64_url_nopad(&self) -> Result<Vec<u8>, FromBase64Error> {
+        self.from_base64()
+    }
+
+    /// Converts the value of `self`, interpreted as base64 encoded data using
+    /// either the standard or the URL safe character set, or even a mix of
+    /// both, into an owned vector of bytes. This is the same as
+    /// `from_base64`.
+    fn from_base64_any(&self) -> Result<Vec<u8>, FromBase64Error> {
+        self.from_base64()
+    }
+
+    /// Like `from_base64`, but rejects the `-` and `_` characters of the URL
+    /// safe character set. This is available for values which can be viewed
+    /// as bytes, such as `str` and `[u8]`.
+    fn from_base64_standard(&self) -> Result<Vec<u8>, FromBase64Error>
+        where Self: AsRef<[u8]>
+    {
+        decode(self.as_ref(), &Standard.decode_table())
+    }
+
+    /// Like `from_base64`, but rejects the `+` and `/` characters of the
+    /// standard character set. This is available for values which can be
+    /// viewed as bytes, such as `str` and `[u8]`.
+    fn from_base64_urlsafe(&self) -> Result<Vec<u8>, FromBase64Error>
+        where Self: AsRef<[u8]>
+    {
+        decode(self.as_ref(), &UrlSafe.decode_table())
+    }
+
+    /// Like `from_base64`, but accepts only the characters of `alphabet`.
+    /// This is available for values which can be viewed as bytes, such as
+    /// `str` and `[u8]`.
+    fn from_base64_with(&self, alphabet: &Alphabet) -> Result<Vec<u8>, FromBase64Error>
+        where Self: AsRef<[u8]>
+    {
+        decode(self.as_ref(), &alphabet.decode_table())
+    }
+
+    /// Like `from_base64`, but accepts only the characters of `char_set` and
+    /// padding, rejecting line breaks and the characters of the other
+    /// character set. This is available for values which can be viewed as
+    /// bytes, such as `str` and `[u8]`.
+    fn from_base64_strict(&self, char_set: CharacterSet) -> Result<Vec<u8>, FromBase64Error>
+        where Self: AsRef<[u8]>
+    {
+        let mut table = char_set.decode_table();
+        table[b'\r' as usize] = INVALID_CODE;
+        table[b'\n' as usize] = INVALID_CODE;
+        decode(self.as_ref(), &table)
+    }
+
+    /// Like `from_base64`, but writes the decoded bytes to the start of `out`
+    /// instead of allocating a vector, and returns how many were written.
+    /// Fails with `BufferTooSmall` if they do not fit, in which case `out`
+    /// may have been partially written.
+    fn decode_into_slice(&self, out: &mut [u8]) -> Result<usize, FromBase64Error> {
+        let bytes = try!(self.from_base64());
+        if bytes.len() > out.len() {
+            return Err(BufferTooSmall);
+        }
+        out[..bytes.len()].copy_from_slice(&bytes);
+        Ok(bytes.len())
+    }
+
+    /// Like `from_base64`, but appends the decoded bytes to `buf`, so that
+    /// one buffer can be reused for many values. If decoding fails, `buf` is
+    /// left as it was.
+    fn from_base64_into(&self, buf: &mut Vec<u8>) -> Result<(), FromBase64Error> {
+        buf.extend_from_slice(&try!(self.from_base64()));
+        Ok(())
+    }
 }
 
 /// Errors that can occur when decoding a base64 encoded string
@@ -211,6 +405,8 @@ pub enum FromBase64Error {
     InvalidBase64Byte(u8, usize),
     /// The input had an invalid length
     InvalidBase64Length,
+    /// The output buffer was too small for the decoded bytes
+    BufferTooSmall,
 }
 
 impl fmt::Debug for FromBase64Error {
@@ -219,6 +415,7 @@ impl fmt::Debug for FromBase64Error {
             InvalidBase64Byte(ch, idx) =>
                 write!(f, "Invalid character '{}' at position {}", ch, idx),
             InvalidBase64Length => write!(f, "Invalid length"),
+            BufferTooSmall => write!(f, "Output buffer too small"),
         }
     }
 }
@@ -228,6 +425,7 @@ impl error::Error for FromBase64Error {
         match *self {
             InvalidBase64Byte(_, _) => "invalid character",
             InvalidBase64Length => "invalid length",
+            BufferTooSmall => "output buffer too small",
         }
     }
 }
@@ -269,57 +467,401 @@ impl FromBase64 for str {
     fn from_base64(&self) -> Result<Vec<u8>, FromBase64Error> {
         self.as_bytes().from_base64()
     }
+
+    #[inline]
+    fn from_base64_standard(&self) -> Result<Vec<u8>, FromBase64Error> {
+        self.as_bytes().from_base64_standard()
+    }
+
+    #[inline]
+    fn from_base64_urlsafe(&self) -> Result<Vec<u8>, FromBase64Error> {
+        self.as_bytes().from_base64_urlsafe()
+    }
+
+    #[inline]
+    fn decode_into_slice(&self, out: &mut [u8]) -> Result<usize, FromBase64Error> {
+        self.as_bytes().decode_into_slice(out)
+    }
+
+    #[inline]
+    fn from_base64_into(&self, buf: &mut Vec<u8>) -> Result<(), FromBase64Error> {
+        self.as_bytes().from_base64_into(buf)
+    }
 }
 
 impl FromBase64 for [u8] {
     fn from_base64(&self) -> Result<Vec<u8>, FromBase64Error> {
-        let mut r = Vec::with_capacity(self.len());
-        let mut buf: u32 = 0;
-        let mut modulus = 0;
+        decode(self, &DECODE_TABLE)
+    }
+
+    fn from_base64_standard(&self) -> Result<Vec<u8>, FromBase64Error> {
+        decode(self, &Standard.decode_table())
+    }
+
+    fn from_base64_urlsafe(&self) -> Result<Vec<u8>, FromBase64Error> {
+        decode(self, &UrlSafe.decode_table())
+    }
+
+    fn decode_into_slice(&self, out: &mut [u8]) -> Result<usize, FromBase64Error> {
+        let mut len = 0;
+        try!(decode_with(self, &DECODE_TABLE, |b| {
+            match out.get_mut(len) {
+                Some(slot) => *slot = b,
+                None => return Err(BufferTooSmall),
+            }
+            len += 1;
+            Ok(())
+        }));
+        Ok(len)
+    }
+
+    fn from_base64_into(&self, buf: &mut Vec<u8>) -> Result<(), FromBase64Error> {
+        let start = buf.len();
+        buf.reserve(decoded_len_estimate(self.len()));
+        let result = decode_with(self, &DECODE_TABLE, |b| { buf.push(b); Ok(()) });
+        if result.is_err() {
+            buf.truncate(start);
+        }
+        result
+    }
+}
+
+/// Decodes `input`, looking up the value of each character in `table`, which
+/// is laid out like `DECODE_TABLE`.
+fn decode(input: &[u8], table: &[u8; 256]) -> Result<Vec<u8>, FromBase64Error> {
+    let mut r = Vec::with_capacity(decoded_len_estimate(input.len()));
+    try!(decode_with(input, table, |b| { r.push(b); Ok(()) }));
+    Ok(r)
+}
+
+/// Like `decode`, but passes each decoded byte to `push`.
+fn decode_with<F>(input: &[u8], table: &[u8; 256], mut push: F) -> Result<(), FromBase64Error>
+    where F: FnMut(u8) -> Result<(), FromBase64Error>
+{
+    let mut state = DecodeState::new();
+    try!(state.feed(input, 0, table, &mut push));
+    state.finish(&mut push)
+}
+
+/// The progress of decoding base64 input which may arrive in pieces.
+struct DecodeState {
+    buf: u32,
+    modulus: usize,
+    // whether padding has been seen, after which only more padding and
+    // newlines may follow
+    padded: bool,
+}
 
-        let mut it = self.iter();
-        for byte in it.by_ref() {
-            let code = DECODE_TABLE[*byte as usize];
+impl DecodeState {
+    fn new() -> DecodeState {
+        DecodeState { buf: 0, modulus: 0, padded: false }
+    }
+
+    /// Decodes the next piece of input, which starts at `offset` in the
+    /// whole input.
+    fn feed<F>(&mut self, input: &[u8], offset: usize, table: &[u8; 256], push: &mut F)
+               -> Result<(), FromBase64Error>
+        where F: FnMut(u8) -> Result<(), FromBase64Error>
+    {
+        for (i, &byte) in input.iter().enumerate() {
+            if self.padded {
+                match table[byte as usize] {
+                    EQUALS_CODE | NEWLINE_CODE => continue,
+                    _ => return Err(InvalidBase64Byte(byte, offset + i)),
+                }
+            }
+            let code = table[byte as usize];
             if code >= SPECIAL_CODES_START {
                 match code {
                     NEWLINE_CODE => continue,
-                    EQUALS_CODE => break,
-                    INVALID_CODE => return Err(InvalidBase64Byte(
-                            *byte, (byte as *const _ as usize) - self.as_ptr() as usize)),
+                    EQUALS_CODE => { self.padded = true; continue }
+                    INVALID_CODE => return Err(InvalidBase64Byte(byte, offset + i)),
                     _ => unreachable!(),
                 }
             }
-            buf = (buf | code as u32) << 6;
-            modulus += 1;
-            if modulus == 4 {
-                modulus = 0;
-                r.push((buf >> 22) as u8);
-                r.push((buf >> 14) as u8);
-                r.push((buf >> 6 ) as u8);
-            }
-        }
-
-        for byte in it {
-            match *byte {
-                b'=' | b'\r' | b'\n' => continue,
-                _ => return Err(InvalidBase64Byte(
-                        *byte, (byte as *const _ as usize) - self.as_ptr() as usize)),
+            self.buf = (self.buf | code as u32) << 6;
+            self.modulus += 1;
+            if self.modulus == 4 {
+                self.modulus = 0;
+                try!(push((self.buf >> 22) as u8));
+                try!(push((self.buf >> 14) as u8));
+                try!(push((self.buf >> 6 ) as u8));
             }
         }
+        Ok(())
+    }
 
-        match modulus {
+    /// Decodes the bytes of an incomplete final group.
+    fn finish<F>(&mut self, push: &mut F) -> Result<(), FromBase64Error>
+        where F: FnMut(u8) -> Result<(), FromBase64Error>
+    {
+        match self.modulus {
             2 => {
-                r.push((buf >> 10) as u8);
+                try!(push((self.buf >> 10) as u8));
             }
             3 => {
-                r.push((buf >> 16) as u8);
-                r.push((buf >> 8 ) as u8);
+                try!(push((self.buf >> 16) as u8));
+                try!(push((self.buf >> 8 ) as u8));
             }
             0 => (),
             _ => return Err(InvalidBase64Length),
         }
+        self.modulus = 0;
+        Ok(())
+    }
+}
 
-        Ok(r)
+/// Writes the base64 encoding of the bytes written to it to an underlying
+/// writer, without holding more than a few bytes of the input at once.
+///
+/// The output is the same as that of `to_base64` with the same `Config`,
+/// however the input is split between writes. Because a final incomplete
+/// group of bytes can only be encoded once the input is known to have
+/// ended, `finish` must be called after the last write.
+pub struct Encoder<W: Write> {
+    inner: W,
+    config: Config,
+    alphabet: Alphabet,
+    // input bytes which do not yet make up a whole group
+    pending: [u8; 3],
+    pending_len: usize,
+    // characters written on the current line
+    line_len: usize,
+    // encoded output the underlying writer has not taken yet
+    unwritten: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates an encoder which writes to `inner` in the format given by
+    /// `config`.
+    pub fn new(inner: W, config: Config) -> Encoder<W> {
+        Encoder::with_alphabet(inner, config, Alphabet::from(config.char_set))
+    }
+
+    /// Creates an encoder which writes to `inner` in the format given by
+    /// `config`, but with the characters of `alphabet` instead of
+    /// `config.char_set`.
+    pub fn with_alphabet(inner: W, config: Config, alphabet: Alphabet) -> Encoder<W> {
+        Encoder {
+            inner: inner,
+            config: config,
+            alphabet: alphabet,
+            pending: [0; 3],
+            pending_len: 0,
+            line_len: 0,
+            unwritten: Vec::new(),
+        }
+    }
+
+    /// Writes the final incomplete group of bytes, with padding if the
+    /// `Config` asks for it, flushes the underlying writer and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        try!(self.write_unwritten());
+        let mut out = [0; GROUP_OUT_LEN];
+        let mut line_len = self.line_len;
+        let pending = self.pending;
+        let len = match self.pending_len {
+            0 => 0,
+            1 => self.encode_group(&mut out, &mut line_len, &[pending[0], 0, 0], 2),
+            _ => self.encode_group(&mut out, &mut line_len, &[pending[0], pending[1], 0], 3),
+        };
+        try!(self.inner.write_all(&out[..len]));
+        try!(self.inner.flush());
+        Ok(self.inner)
+    }
+
+    /// Writes the output which an earlier call to `write` encoded but the
+    /// underlying writer did not take.
+    fn write_unwritten(&mut self) -> io::Result<()> {
+        let (n, result) = write_some(&mut self.inner, &self.unwritten);
+        self.unwritten.drain(..n);
+        result
+    }
+
+    /// Writes the first `chars` characters of the encoding of `group`, padded
+    /// to four, to the start of `out`, breaking the line first if the
+    /// `line_len` characters on it fill it. Returns the length written.
+    fn encode_group(&self, out: &mut [u8], line_len: &mut usize, group: &[u8; 3],
+                    chars: usize) -> usize {
+        let bytes = self.alphabet.chars;
+        let mut len = 0;
+        if let Some(line_length) = self.config.line_length {
+            if *line_len >= line_length {
+                let newline: &[u8] = match self.config.newline {
+                    Newline::LF => b"\n",
+                    Newline::CRLF => b"\r\n",
+                };
+                out[..newline.len()].copy_from_slice(newline);
+                len = newline.len();
+                *line_len = 0;
+            }
+        }
+        let n = (group[0] as u32) << 16 | (group[1] as u32) << 8 | group[2] as u32;
+        for i in 0..4 {
+            if i < chars {
+                out[len] = bytes[((n >> (18 - 6 * i)) & 63) as usize];
+                len += 1;
+            } else if self.config.pad {
+                out[len] = b'=';
+                len += 1;
+            }
+        }
+        *line_len += 4;
+        len
+    }
+}
+
+// The most groups of input encoded by one call to `Encoder::write`, and the
+// most output written for one group, including a line break.
+const GROUPS_PER_WRITE: usize = 256;
+const GROUP_OUT_LEN: usize = 6;
+
+// Writes as much of `buf` to `inner` as it takes before failing, returning
+// the number of bytes written and the error, if any.
+fn write_some<W: Write>(inner: &mut W, buf: &[u8]) -> (usize, io::Result<()>) {
+    let mut written = 0;
+    while written < buf.len() {
+        match inner.write(&buf[written..]) {
+            Ok(0) => {
+                let err = io::Error::new(io::ErrorKind::WriteZero, "failed to write encoded data");
+                return (written, Err(err));
+            }
+            Ok(n) => written += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return (written, Err(e)),
+        }
+    }
+    (written, Ok(()))
+}
+
+/// A call to `write` consumes at most a few hundred bytes, which it encodes
+/// on the stack. Once the bytes are encoded they are consumed, even if the
+/// underlying writer fails to take all of their encoding; the rest of it is
+/// held and written first by the next call to `write`, `flush` or `finish`,
+/// which reports the error if the underlying writer fails again.
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_unwritten());
+        let mut out = [0; GROUPS_PER_WRITE * GROUP_OUT_LEN];
+        let mut len = 0;
+        let mut pending = self.pending;
+        let mut pending_len = self.pending_len;
+        let mut line_len = self.line_len;
+        let buf = &buf[..cmp::min(buf.len(), GROUPS_PER_WRITE * 3 - pending_len)];
+        for &b in buf {
+            pending[pending_len] = b;
+            pending_len += 1;
+            if pending_len == 3 {
+                len += self.encode_group(&mut out[len..], &mut line_len, &pending, 4);
+                pending_len = 0;
+            }
+        }
+        self.pending = pending;
+        self.pending_len = pending_len;
+        self.line_len = line_len;
+        let (n, _) = write_some(&mut self.inner, &out[..len]);
+        self.unwritten.extend_from_slice(&out[n..len]);
+        Ok(buf.len())
+    }
+
+    /// Flushes the underlying writer. An incomplete group of bytes is held
+    /// back until more are written or the encoder is finished.
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_unwritten());
+        self.inner.flush()
+    }
+}
+
+/// Reads the bytes decoded from the base64 text read from an underlying
+/// reader, as they become available.
+///
+/// Unless created with `with_char_set` or `with_alphabet`, this accepts the
+/// same input as `from_base64`. Invalid input is reported as an error of
+/// kind `InvalidData` wrapping the `FromBase64Error`, whose position is
+/// counted from the start of the input. Once the bytes decoded before it
+/// have been read, every read reports the error.
+pub struct Decoder<R: Read> {
+    inner: R,
+    table: [u8; 256],
+    state: DecodeState,
+    // base64 bytes read from `inner` so far
+    offset: usize,
+    // decoded bytes which have not been read yet
+    out: Vec<u8>,
+    out_pos: usize,
+    done: bool,
+    // invalid input found, reported by every read once `out` is drained
+    error: Option<FromBase64Error>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a decoder which reads base64 text from `inner`.
+    pub fn new(inner: R) -> Decoder<R> {
+        Decoder::with_table(inner, DECODE_TABLE)
+    }
+
+    /// Creates a decoder which reads base64 text from `inner`, like
+    /// `from_base64_standard` or `from_base64_urlsafe` depending on
+    /// `char_set`.
+    pub fn with_char_set(inner: R, char_set: CharacterSet) -> Decoder<R> {
+        Decoder::with_table(inner, char_set.decode_table())
+    }
+
+    /// Creates a decoder which reads base64 text from `inner`, like
+    /// `from_base64_with(alphabet)`.
+    pub fn with_alphabet(inner: R, alphabet: Alphabet) -> Decoder<R> {
+        Decoder::with_table(inner, alphabet.decode_table())
+    }
+
+    fn with_table(inner: R, table: [u8; 256]) -> Decoder<R> {
+        Decoder {
+            inner: inner,
+            table: table,
+            state: DecodeState::new(),
+            offset: 0,
+            out: Vec::new(),
+            out_pos: 0,
+            done: false,
+            error: None,
+        }
+    }
+
+    /// Unwraps this decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = [0; 1024];
+        while self.out_pos == self.out.len() {
+            if let Some(e) = self.error {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            if self.done {
+                return Ok(0);
+            }
+            self.out.clear();
+            self.out_pos = 0;
+            let n = try!(self.inner.read(&mut input));
+            let out = &mut self.out;
+            let mut push = |b| { out.push(b); Ok(()) };
+            let result = if n == 0 {
+                self.done = true;
+                self.state.finish(&mut push)
+            } else {
+                self.state.feed(&input[..n], self.offset, &self.table, &mut push)
+            };
+            self.offset += n;
+            if let Err(e) = result {
+                self.error = Some(e);
+            }
+        }
+        let n = cmp::min(buf.len(), self.out.len() - self.out_pos);
+        buf[..n].copy_from_slice(&self.out[self.out_pos..self.out_pos + n]);
+        self.out_pos += n;
+        Ok(n)
     }
 }
 
@@ -327,6 +869,14 @@ impl<'a, T: ?Sized + FromBase64> FromBase64 for &'a T {
     fn from_base64(&self) -> Result<Vec<u8>, FromBase64Error> {
         (**self).from_base64()
     }
+
+    fn decode_into_slice(&self, out: &mut [u8]) -> Result<usize, FromBase64Error> {
+        (**self).decode_into_slice(out)
+    }
+
+    fn from_base64_into(&self, buf: &mut Vec<u8>) -> Result<(), FromBase64Error> {
+        (**self).from_base64_into(buf)
+    }
 }
 
 /// Base64 decoding lookup table, generated using:
@@ -377,7 +927,8 @@ const SPECIAL_CODES_START: u8 = NEWLINE_CODE;
 
 #[cfg(test)]
 mod tests {
-    use base64::{Config, Newline, FromBase64, ToBase64, STANDARD, URL_SAFE};
+    use base64::{Config, Newline, FromBase64, ToBase64, STANDARD, URL_SAFE, MIME, PEM,
+                 BufferTooSmall};
 
     #[test]
     fn test_to_base64_basic() {
@@ -411,6 +962,166 @@ mod tests {
                    "Zm9v\nYmFy");
     }
 
+    #[test]
+    fn test_from_base64_alphabets() {
+        use base64::{CharacterSet, FromBase64Error};
+
+        // 0xfb 0xff encodes to "+/8" and "-_8" in the two character sets
+        assert_eq!("+/8=".from_base64_any().unwrap(), [0xfb, 0xff]);
+        assert_eq!("-_8=".from_base64_any().unwrap(), [0xfb, 0xff]);
+        assert_eq!("+_8".from_base64_any().unwrap(), [0xfb, 0xff]);
+
+        assert_eq!("+/8=".from_base64_standard().unwrap(), [0xfb, 0xff]);
+        assert!(b"-_8=".from_base64_standard().is_err());
+        assert!("+_8=".from_base64_standard().is_err());
+
+        assert_eq!("-_8".from_base64_urlsafe().unwrap(), [0xfb, 0xff]);
+        assert!("+/8".from_base64_urlsafe().is_err());
+        assert!("-/8".from_base64_urlsafe().is_err());
+        assert_eq!((&"+/8=").from_base64_standard().unwrap(), [0xfb, 0xff]);
+        assert!((&&b"+/8"[..]).from_base64_urlsafe().is_err());
+
+        // implementations outside this module need not provide these
+        struct Text(String);
+        impl AsRef<[u8]> for Text {
+            fn as_ref(&self) -> &[u8] { self.0.as_bytes() }
+        }
+        impl FromBase64 for Text {
+            fn from_base64(&self) -> Result<Vec<u8>, FromBase64Error> {
+                self.0.from_base64()
+            }
+        }
+        assert_eq!(Text("-_8".to_string()).from_base64().unwrap(), [0xfb, 0xff]);
+        assert!(Text("-_8".to_string()).from_base64_standard().is_err());
+        assert!(Text("-_8".to_string()).from_base64_strict(CharacterSet::Standard).is_err());
+    }
+
+    #[test]
+    fn test_custom_alphabet() {
+        use std::io::{Read, Write};
+        use base64::{Alphabet, Encoder, Decoder, Standard, UrlSafe};
+
+        const CRYPT: &'static [u8] =
+            b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+        let crypt = Alphabet::new(CRYPT).unwrap();
+
+        assert_eq!([0, 0x10, 0x83].to_base64_with(&crypt, STANDARD), "./01");
+        assert_eq!([0xff, 0xfe].to_base64_with(&crypt, STANDARD), "zzs=");
+        let data: Vec<u8> = (0..=255).collect();
+        let encoded = data.to_base64_with(&crypt, MIME);
+        assert_eq!(encoded.from_base64_with(&crypt).unwrap(), data);
+        assert_eq!((&&encoded[..]).from_base64_with(&crypt).unwrap(), data);
+
+        let mut encoder = Encoder::with_alphabet(Vec::new(), MIME, crypt);
+        encoder.write_all(&data).unwrap();
+        assert_eq!(encoder.finish().unwrap(), encoded.as_bytes());
+        let mut out = Vec::new();
+        Decoder::with_alphabet(encoded.as_bytes(), crypt).read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+        assert!(Decoder::with_alphabet(&b"+/8="[..], crypt).read_to_end(&mut out).is_err());
+
+        assert!("..0+".from_base64_with(&crypt).is_err());
+        let standard = Alphabet::from(Standard);
+        assert_eq!("+/8=".from_base64_with(&standard).unwrap(), [0xfb, 0xff]);
+        assert!("+/8=".from_base64_with(&Alphabet::from(UrlSafe)).is_err());
+        assert_eq!([0xfb, 0xff].to_base64_with(&standard, URL_SAFE), "+/8");
+
+        assert!(Alphabet::new(&CRYPT[1..]).is_none());
+        let mut dup = CRYPT.to_vec();
+        dup[1] = b'.';
+        assert!(Alphabet::new(&dup).is_none());
+        for &bad in &[b'=', b' ', b'\n', 0xc3] {
+            let mut chars = CRYPT.to_vec();
+            chars[0] = bad;
+            assert!(Alphabet::new(&chars).is_none());
+        }
+    }
+
+    #[test]
+    fn test_decode_into_slice() {
+        let mut out = [0u8; 6];
+        assert_eq!("Zm9vYmFy".decode_into_slice(&mut out).unwrap(), 6);
+        assert_eq!(&out, b"foobar");
+
+        let mut out = [0u8; 8];
+        assert_eq!("Zm9v\r\nYg==".decode_into_slice(&mut out).unwrap(), 4);
+        assert_eq!(&out[..4], b"foob");
+        assert_eq!(b"".decode_into_slice(&mut []).unwrap(), 0);
+
+        let mut out = [0u8; 5];
+        match "Zm9vYmFy".decode_into_slice(&mut out) {
+            Err(BufferTooSmall) => (),
+            other => panic!("expected BufferTooSmall, got {:?}", other),
+        }
+        assert!("Zm9vYm$y".decode_into_slice(&mut [0u8; 6]).is_err());
+    }
+
+    #[test]
+    fn test_from_base64_into() {
+        let mut buf = b"x".to_vec();
+        "Zm9v".from_base64_into(&mut buf).unwrap();
+        b"YmFy\r\n".from_base64_into(&mut buf).unwrap();
+        assert_eq!(buf, b"xfoobar");
+        assert!("Zm9vYm$y".from_base64_into(&mut buf).is_err());
+        assert_eq!(buf, b"xfoobar");
+        (&"Zg==").from_base64_into(&mut buf).unwrap();
+        assert_eq!(buf, b"xfoobarf");
+
+        buf.clear();
+        let capacity = buf.capacity();
+        "Zm9v".from_base64_into(&mut buf).unwrap();
+        assert_eq!(buf, b"foo");
+        assert_eq!(buf.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_base64_url_nopad() {
+        let header = b"{\"alg\":\"HS256\",\"typ\":\"JWT\"}";
+        let payload = b"{\"sub\":\"1234567890\",\"name\":\"John Doe\",\"iat\":1516239022}";
+        let encoded_payload = "eyJzdWIiOiIxMjM0NTY3ODkwIiwibmFtZSI6IkpvaG4gRG9lIiwiaWF0IjoxNTE2MjM5MDIyfQ";
+
+        assert_eq!(header.to_base64_url_nopad(), "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9");
+        assert_eq!(payload.to_base64_url_nopad(), encoded_payload);
+        assert_eq!(encoded_payload.from_base64_url_nopad().unwrap(), &payload[..]);
+        assert_eq!("_-8".from_base64_url_nopad().unwrap(), [0xff, 0xef]);
+        assert_eq!("_-8=".from_base64_url_nopad().unwrap(), [0xff, 0xef]);
+        assert!("_-8?".from_base64_url_nopad().is_err());
+    }
+
+    #[test]
+    fn test_to_base64_pem() {
+        let encoded = [0; 100].to_base64(PEM);
+        let lines: Vec<&str> = encoded.split('\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), 64);
+        assert_eq!(lines[1].len(), 64);
+        assert_eq!(lines[2], "AAAAAA==");
+        assert!(!encoded.contains("\r"));
+        assert_eq!(encoded.from_base64().unwrap(), &[0; 100][..]);
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        use base64::{encoded_len, decoded_len_estimate};
+
+        let configs = [STANDARD, URL_SAFE, MIME, PEM,
+                       Config {line_length: Some(5), ..STANDARD},
+                       Config {line_length: Some(1), pad: false, ..PEM},
+                       Config {line_length: Some(0), ..STANDARD}];
+        for config in configs.iter() {
+            for len in 0..200 {
+                let encoded = [0xa5; 200][..len].to_base64(*config);
+                assert_eq!(encoded_len(len, *config), encoded.len());
+                let decoded = encoded.from_base64().unwrap();
+                assert!(decoded_len_estimate(encoded.len()) >= decoded.len());
+            }
+        }
+        assert_eq!(b"foob".to_base64(Config {line_length: Some(5), ..STANDARD}),
+                   "Zm9vYg==");
+        assert_eq!(decoded_len_estimate(8), 6);
+        assert_eq!(decoded_len_estimate(0), 0);
+    }
+
     #[test]
     fn test_to_base64_padding() {
         assert_eq!("f".as_bytes().to_base64(Config {pad: false, ..STANDARD}), "Zg");
@@ -456,6 +1167,29 @@ mod tests {
                    b"foob");
     }
 
+    #[test]
+    fn test_from_base64_strict() {
+        use base64::{InvalidBase64Byte, Standard, UrlSafe};
+
+        assert_eq!("Zm9vYg==".from_base64_strict(Standard).unwrap(), b"foob");
+        assert_eq!("+/8=".from_base64_strict(Standard).unwrap(), [0xfb, 0xff]);
+        assert_eq!(b"-_8".from_base64_strict(UrlSafe).unwrap(), [0xfb, 0xff]);
+        assert_eq!((&"").from_base64_strict(Standard).unwrap(), b"");
+        for &(input, pos) in &[("Zm9v\r\nYmFy", 4), ("Zm9vYg==\n", 8), ("Zm9v YmFy", 4),
+                               ("\nZm9v", 0), ("+_8=", 1), ("-_8=", 0)] {
+            match input.from_base64_strict(Standard) {
+                Err(InvalidBase64Byte(_, p)) => assert_eq!(p, pos, "{:?}", input),
+                other => panic!("unexpected result for {:?}: {:?}", input, other),
+            }
+        }
+        match "-/8".from_base64_strict(UrlSafe) {
+            Err(InvalidBase64Byte(b'/', 1)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // the default stays lenient
+        assert_eq!("Zm9v\r\nYmFy".from_base64().unwrap(), b"foobar");
+    }
+
     #[test]
     fn test_from_base64_urlsafe() {
         assert_eq!("-_8".from_base64().unwrap(), "+/8=".from_base64().unwrap());
@@ -472,6 +1206,166 @@ mod tests {
         assert!("Z===".from_base64().is_err());
     }
 
+    #[test]
+    fn test_encoder_matches_to_base64() {
+        use std::io::Write;
+        use base64::Encoder;
+
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+        let configs = [STANDARD, URL_SAFE, MIME, PEM,
+                       Config {line_length: Some(5), ..STANDARD},
+                       Config {line_length: Some(4), pad: false, ..URL_SAFE}];
+        for config in configs.iter() {
+            for &len in &[0, 1, 2, 3, 4, 47, 48, 49, 300] {
+                for &chunk in &[1, 2, 5, 64, 1000] {
+                    let mut encoder = Encoder::new(Vec::new(), *config);
+                    for piece in data[..len].chunks(chunk) {
+                        encoder.write_all(piece).unwrap();
+                    }
+                    let out = encoder.finish().unwrap();
+                    assert_eq!(String::from_utf8(out).unwrap(),
+                               data[..len].to_base64(*config));
+                }
+            }
+        }
+
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 13) as u8).collect();
+        let mut encoder = Encoder::new(Vec::new(), MIME);
+        encoder.write_all(&data).unwrap();
+        assert_eq!(encoder.finish().unwrap(), data.to_base64(MIME).as_bytes());
+    }
+
+    #[test]
+    fn test_encoder_write_error() {
+        use std::io::{self, Write};
+        use base64::Encoder;
+
+        // Fails every other write of the first kilobyte, without writing
+        // anything.
+        struct Flaky { out: Vec<u8>, fail: bool }
+
+        impl Write for Flaky {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.fail = !self.fail && self.out.len() < 1024;
+                if self.fail {
+                    return Err(io::Error::new(io::ErrorKind::Other, "flaky"));
+                }
+                self.out.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        let data: Vec<u8> = (0..2000u32).map(|i| (i * 7) as u8).collect();
+        let mut encoder = Encoder::new(Flaky { out: Vec::new(), fail: false }, MIME);
+        for piece in data.chunks(100) {
+            let mut piece = piece;
+            while !piece.is_empty() {
+                match encoder.write(piece) {
+                    Ok(n) => piece = &piece[n..],
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::Other),
+                }
+            }
+        }
+        let out = encoder.finish().unwrap().out;
+        assert_eq!(out, data.to_base64(MIME).as_bytes());
+
+        // Takes part of every other write of the first two kilobytes, and fails
+        // the rest.
+        struct Partial { out: Vec<u8>, fail: bool }
+
+        impl Write for Partial {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.fail = !self.fail && self.out.len() < 2048;
+                if self.fail {
+                    return Err(io::Error::new(io::ErrorKind::Other, "partial"));
+                }
+                let n = if buf.len() < 7 { buf.len() } else { 7 };
+                self.out.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        let mut encoder = Encoder::new(Partial { out: Vec::new(), fail: false }, MIME);
+        for piece in data.chunks(100) {
+            let mut piece = piece;
+            while !piece.is_empty() {
+                match encoder.write(piece) {
+                    Ok(n) => piece = &piece[n..],
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::Other),
+                }
+            }
+        }
+        while encoder.flush().is_err() {}
+        let out = encoder.finish().unwrap().out;
+        assert_eq!(out, data.to_base64(MIME).as_bytes());
+    }
+
+    #[test]
+    fn test_decoder() {
+        use std::io::{self, Read};
+        use base64::{Decoder, InvalidBase64Byte, FromBase64Error, UrlSafe};
+
+        // Returns its input one byte per read.
+        struct Trickle<'a>(&'a [u8]);
+
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.split_first() {
+                    Some((&b, rest)) if !buf.is_empty() => {
+                        buf[0] = b;
+                        self.0 = rest;
+                        Ok(1)
+                    }
+                    _ => Ok(0),
+                }
+            }
+        }
+
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+        for &len in &[0, 1, 2, 3, 100, 300] {
+            let encoded = data[..len].to_base64(MIME);
+            let mut out = Vec::new();
+            Decoder::new(encoded.as_bytes()).read_to_end(&mut out).unwrap();
+            assert_eq!(out, &data[..len]);
+
+            let mut out = Vec::new();
+            Decoder::new(Trickle(encoded.as_bytes())).read_to_end(&mut out).unwrap();
+            assert_eq!(out, &data[..len]);
+        }
+
+        let mut buf = [0; 2];
+        let mut decoder = Decoder::new(Trickle(b"Zm9v\r\nYmFy"));
+        assert_eq!(decoder.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf, b"fo");
+
+        for &(input, pos) in &[(&b"Zm9vYm$y"[..], 6), (&b"Zg==\nZg"[..], 5)] {
+            let err = Decoder::new(Trickle(input)).read_to_end(&mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            match *err.get_ref().unwrap().downcast_ref::<FromBase64Error>().unwrap() {
+                InvalidBase64Byte(_, p) => assert_eq!(p, pos),
+                e => panic!("unexpected error: {:?}", e),
+            }
+        }
+        assert!(Decoder::new(&b"Z==="[..]).read_to_end(&mut Vec::new()).is_err());
+
+        // The bytes decoded before invalid input are read, then every read
+        // fails.
+        let mut decoder = Decoder::new(&b"Zm9vYm$y"[..]);
+        let mut buf = [0; 8];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"foo");
+        for _ in 0..2 {
+            assert_eq!(decoder.read(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        let mut out = Vec::new();
+        Decoder::with_char_set(&b"-_8"[..], UrlSafe).read_to_end(&mut out).unwrap();
+        assert_eq!(out, [0xfb, 0xff]);
+        assert!(Decoder::with_char_set(&b"+/8="[..], UrlSafe).read_to_end(&mut out).is_err());
+    }
+
     #[test]
     fn test_base64_random() {
         use rand::{thread_rng, Rng};
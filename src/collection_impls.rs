@@ -13,7 +13,9 @@
 use std::hash::Hash;
 
 use {Decodable, Encodable, Decoder, Encoder, cap_capacity};
-use std::collections::{LinkedList, VecDeque, BTreeMap, BTreeSet, HashMap, HashSet};
+use json::{self, Json};
+use std::collections::{LinkedList, VecDeque, BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 impl<
     T: Encodable
@@ -63,6 +65,31 @@ impl<T:Decodable> Decodable for VecDeque<T> {
     }
 }
 
+// A `BinaryHeap` is encoded in its internal order, so only its elements, not
+// their order, survive a round trip.
+impl<T: Encodable> Encodable for BinaryHeap<T> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_seq(self.len(), |s| {
+            for (i, e) in self.iter().enumerate() {
+                try!(s.emit_seq_elt(i, |s| e.encode(s)));
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<T: Decodable + Ord> Decodable for BinaryHeap<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<BinaryHeap<T>, D::Error> {
+        d.read_seq(|d, len| {
+            let mut heap = BinaryHeap::with_capacity(cap_capacity::<T>(len));
+            for i in 0..len {
+                heap.push(try!(d.read_seq_elt(i, |d| Decodable::decode(d))));
+            }
+            Ok(heap)
+        })
+    }
+}
+
 impl<
     K: Encodable + Ord,
     V: Encodable
@@ -131,6 +158,19 @@ impl<K, V> Encodable for HashMap<K, V>
           V: Encodable,
 {
     fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        if e.sort_keys() {
+            let mut entries: Vec<_> = self.iter().map(|(key, val)| {
+                (sort_key(key), key, val)
+            }).collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            return e.emit_map(entries.len(), |e| {
+                for (i, &(_, key, val)) in entries.iter().enumerate() {
+                    try!(e.emit_map_elt_key(i, |e| key.encode(e)));
+                    try!(e.emit_map_elt_val(i, |e| val.encode(e)));
+                }
+                Ok(())
+            })
+        }
         e.emit_map(self.len(), |e| {
             let mut i = 0;
             for (key, val) in self.iter() {
@@ -143,6 +183,20 @@ impl<K, V> Encodable for HashMap<K, V>
     }
 }
 
+/// The key by which `HashMap` entries are sorted, as keys need not be `Ord`:
+/// the string itself for keys which encode as a string, or their JSON text
+/// otherwise. Keys which cannot be encoded come first.
+fn sort_key<K: Encodable>(key: &K) -> Option<String> {
+    let text = match json::encode(key) {
+        Ok(text) => text,
+        Err(_) => return None,
+    };
+    match Json::from_str(&text) {
+        Ok(Json::String(s)) => Some(s),
+        _ => Some(text),
+    }
+}
+
 impl<K, V> Decodable for HashMap<K, V>
     where K: Decodable + Hash + Eq,
           V: Decodable,
@@ -184,3 +238,49 @@ impl<T> Decodable for HashSet<T> where T: Decodable + Hash + Eq, {
         })
     }
 }
+
+// A `SystemTime` is encoded as the number of whole seconds since the Unix
+// epoch, negative for earlier times, plus the nanoseconds from there.
+impl Encodable for SystemTime {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                match d.subsec_nanos() {
+                    0 => (-(d.as_secs() as i64), 0),
+                    n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+                }
+            }
+        };
+        s.emit_struct("SystemTime", 2, |s| {
+            try!(s.emit_struct_field("secs_since_epoch", 0, |s| secs.encode(s)));
+            try!(s.emit_struct_field("nanos_since_epoch", 1, |s| nanos.encode(s)));
+            Ok(())
+        })
+    }
+}
+
+impl Decodable for SystemTime {
+    fn decode<D: Decoder>(d: &mut D) -> Result<SystemTime, D::Error> {
+        d.read_struct("SystemTime", 2, |d| {
+            let secs: i64 = try!(d.read_struct_field("secs_since_epoch", 0,
+                                                     Decodable::decode));
+            let nanos: u32 = try!(d.read_struct_field("nanos_since_epoch", 1,
+                                                      Decodable::decode));
+            if nanos >= 1_000_000_000 {
+                return Err(d.error("nanos_since_epoch out of range"));
+            }
+            let time = if secs >= 0 {
+                UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+            } else {
+                UNIX_EPOCH.checked_sub(Duration::new(secs.wrapping_neg() as u64, 0))
+                          .and_then(|t| t.checked_add(Duration::new(0, nanos)))
+            };
+            match time {
+                Some(time) => Ok(time),
+                None => Err(d.error("SystemTime out of range")),
+            }
+        })
+    }
+}
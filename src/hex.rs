@@ -67,11 +67,12 @@ pub trait FromHex {
 }
 
 /// Errors that can occur when decoding a hex encoded string
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum FromHexError {
-    /// The input contained a character not part of the hex format
+    /// The input contained a character not part of the hex format, at the
+    /// given byte index
     InvalidHexCharacter(char, usize),
-    /// The input had an invalid length
+    /// The input had an odd number of hex digits
     InvalidHexLength,
 }
 
@@ -96,7 +97,12 @@ impl error::Error for FromHexError {
 
 impl fmt::Display for FromHexError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&self, f)
+        match *self {
+            InvalidHexCharacter(ch, idx) =>
+                write!(f, "invalid hex character {:?} at index {}", ch, idx),
+            InvalidHexLength =>
+                write!(f, "invalid hex length: odd number of hex digits"),
+        }
     }
 }
 
@@ -169,7 +175,7 @@ impl<'a, T: ?Sized + FromHex> FromHex for &'a T {
 
 #[cfg(test)]
 mod tests {
-    use hex::{FromHex, ToHex};
+    use hex::{FromHex, ToHex, InvalidHexCharacter, InvalidHexLength};
 
     #[test]
     pub fn test_to_hex() {
@@ -188,11 +194,18 @@ mod tests {
     pub fn test_from_hex_odd_len() {
         assert!("666".from_hex().is_err());
         assert!("66 6".from_hex().is_err());
+        assert_eq!("66 6".from_hex(), Err(InvalidHexLength));
+        assert_eq!(InvalidHexLength.to_string(),
+                   "invalid hex length: odd number of hex digits");
     }
 
     #[test]
     pub fn test_from_hex_invalid_char() {
         assert!("66y6".from_hex().is_err());
+        assert_eq!("66y6".from_hex(), Err(InvalidHexCharacter('y', 2)));
+        assert_eq!("6 é6".from_hex(), Err(InvalidHexCharacter('é', 2)));
+        assert_eq!(InvalidHexCharacter('y', 2).to_string(),
+                   "invalid hex character 'y' at index 2");
     }
 
     #[test]
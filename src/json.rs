@@ -244,20 +244,28 @@ use self::DecoderError::*;
 use self::ParserState::*;
 use self::InternalStackElement::*;
 
-use std::collections::{HashMap, BTreeMap};
+use std::cmp::{self, Ordering};
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet, btree_map};
 use std::error::Error as StdError;
+use std::hash::{Hash, Hasher};
 use std::i64;
 use std::io::prelude::*;
 use std::mem::swap;
 use std::ops::Index;
+use std::rc::Rc;
 use std::str::FromStr;
 use std::string;
+use std::sync::Arc;
+use std::vec;
+use std::fmt::Write as FmtWrite;
 use std::{char, f64, fmt, io, str};
 
 use Encodable;
+use base64::{FromBase64, FromBase64Error};
+use hex::{FromHex, FromHexError};
 
 /// Represents a json value
-#[derive(Clone, PartialEq, PartialOrd, Debug)]
+#[derive(Clone, Debug)]
 pub enum Json {
     I64(i64),
     U64(u64),
@@ -272,10 +280,43 @@ pub enum Json {
 pub type Array = Vec<Json>;
 pub type Object = BTreeMap<string::String, Json>;
 
+/// A minimal description of the expected shape of a Json value, checked
+/// with `Json::validate`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum JsonSchema {
+    /// Any value is accepted.
+    Any,
+    Null,
+    Boolean,
+    /// Any of `I64`, `U64` or `F64`.
+    Number,
+    String,
+    /// element schema
+    Array(Box<JsonSchema>),
+    /// property schemas, required keys
+    Object(BTreeMap<string::String, JsonSchema>, Vec<string::String>),
+}
+
+/// A violation of a `JsonSchema`, located by an RFC 6901 JSON Pointer.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SchemaError {
+    /// pointer, expected kind, found kind
+    TypeMismatch(string::String, &'static str, &'static str),
+    /// pointer to the object, missing key
+    MissingKey(string::String, string::String),
+}
+
 pub struct PrettyJson<'a> { inner: &'a Json }
 
 pub struct AsJson<'a, T: 'a> { inner: &'a T }
-pub struct AsPrettyJson<'a, T: 'a> { inner: &'a T, indent: Option<u32> }
+pub struct AsPrettyJson<'a, T: 'a> {
+    inner: &'a T,
+    indent: Option<u32>,
+    indent_str: Option<&'a str>,
+    depth: u32,
+    colored: bool,
+    align_values: bool,
+}
 
 /// The errors that can arise while parsing a JSON stream.
 #[derive(Clone, Copy, PartialEq)]
@@ -286,7 +327,10 @@ pub enum ErrorCode {
     EOFWhileParsingArray,
     EOFWhileParsingValue,
     EOFWhileParsingString,
+    EOFWhileParsingEscape,
+    EOFWhileParsingComment,
     KeyMustBeAString,
+    DuplicateKey,
     ExpectedColon,
     TrailingCharacters,
     TrailingComma,
@@ -298,13 +342,20 @@ pub enum ErrorCode {
     NotFourDigit,
     ControlCharacterInString,
     NotUtf8,
+    RecursionLimitExceeded,
 }
 
 #[derive(Debug)]
 pub enum ParserError {
     /// msg, line, col
     SyntaxError(ErrorCode, usize, usize),
+    /// msg, line, col, byte offset; reported instead of `SyntaxError` when
+    /// `ParserConfig::report_offsets` is set
+    SyntaxErrorAt(ErrorCode, usize, usize, usize),
     IoError(io::Error),
+    /// error encoding the output of `transform_array`, other than one
+    /// writing it
+    EncodeError(EncoderError),
 }
 
 impl PartialEq for ParserError {
@@ -312,43 +363,158 @@ impl PartialEq for ParserError {
         match (self, other) {
             (&SyntaxError(msg0, line0, col0), &SyntaxError(msg1, line1, col1)) =>
                 msg0 == msg1 && line0 == line1 && col0 == col1,
-            (&IoError(_), _) => false,
-            (_, &IoError(_)) => false,
+            (&SyntaxErrorAt(msg0, line0, col0, offset0),
+             &SyntaxErrorAt(msg1, line1, col1, offset1)) =>
+                msg0 == msg1 && line0 == line1 && col0 == col1 && offset0 == offset1,
+            (&EncodeError(ref e0), &EncodeError(ref e1)) => e0 == e1,
+            _ => false,
+        }
+    }
+}
+
+impl ParserError {
+    /// Returns the line of the input on which a syntax error was found.
+    pub fn line(&self) -> Option<usize> {
+        match *self {
+            SyntaxError(_, line, _) | SyntaxErrorAt(_, line, _, _) => Some(line),
+            IoError(_) | EncodeError(_) => None,
+        }
+    }
+
+    /// Returns the column of the input at which a syntax error was found.
+    pub fn column(&self) -> Option<usize> {
+        match *self {
+            SyntaxError(_, _, col) | SyntaxErrorAt(_, _, col, _) => Some(col),
+            IoError(_) | EncodeError(_) => None,
         }
     }
+
+    /// Returns the byte offset into the input at which a syntax error was
+    /// found, if the parser was configured to report it.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            SyntaxErrorAt(_, _, _, offset) => Some(offset),
+            SyntaxError(..) | IoError(_) | EncodeError(_) => None,
+        }
+    }
+}
+
+/// A use of one of the extensions to JSON which a `ParserConfig` can
+/// allow, reported by `Parser::warnings` when `collect_warnings` is set.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Warning {
+    /// line, col of the comma
+    TrailingComma(usize, usize),
+    /// line, col of the start of the comment
+    Comment(usize, usize),
+    /// line, col of a `NaN`, `Infinity` or `-Infinity` literal
+    NonFiniteNumber(usize, usize),
+    /// line, col of an object key which is not quoted
+    UnquotedKey(usize, usize),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (what, line, col) = match *self {
+            Warning::TrailingComma(line, col) => ("trailing comma", line, col),
+            Warning::Comment(line, col) => ("comment", line, col),
+            Warning::NonFiniteNumber(line, col) => ("non-finite number", line, col),
+            Warning::UnquotedKey(line, col) => ("unquoted key", line, col),
+        };
+        write!(f, "{} at line {} column {}", what, line, col)
+    }
 }
 
 // Builder and Parser have the same errors.
 pub type BuilderError = ParserError;
 
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
 pub enum DecoderError {
     ParseError(ParserError),
     ExpectedError(string::String, string::String),
     MissingFieldError(string::String),
     UnknownVariantError(string::String),
+    /// unknown variant, names of the expected variants
+    UnexpectedVariantError(string::String, Vec<string::String>),
+    /// key which appeared twice in a map, as JSON
+    DuplicateKeyError(string::String),
     ApplicationError(string::String),
     EOF,
 }
 
-#[derive(Copy, Debug)]
+impl PartialEq for DecoderError {
+    fn eq(&self, other: &DecoderError) -> bool {
+        match (self, other) {
+            // io errors are never equal, as with `ParserError`
+            (&ParseError(ref a), &ParseError(ref b)) => a == b,
+            (&ExpectedError(ref a0, ref a1), &ExpectedError(ref b0, ref b1)) => {
+                a0 == b0 && a1 == b1
+            }
+            (&MissingFieldError(ref a), &MissingFieldError(ref b)) => a == b,
+            (&UnknownVariantError(ref a), &UnknownVariantError(ref b)) => a == b,
+            (&UnexpectedVariantError(ref a0, ref a1),
+             &UnexpectedVariantError(ref b0, ref b1)) => a0 == b0 && a1 == b1,
+            (&DuplicateKeyError(ref a), &DuplicateKeyError(ref b)) => a == b,
+            (&ApplicationError(ref a), &ApplicationError(ref b)) => a == b,
+            (&EOF, &EOF) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The errors that can arise while encoding a JSON value.
+///
+/// Since `IoError` was added in 0.4 this type is no longer `Copy`, as
+/// `io::Error` is not; code that copied an `EncoderError` out of a reference
+/// must now `clone` it.
+#[derive(Debug)]
 pub enum EncoderError {
     FmtError(fmt::Error),
     BadHashmapKey,
+    /// An error writing to an `io::Write`.
+    ///
+    /// `io::Error` is not `Clone`, so a clone of this variant holds a new
+    /// error with the same kind and message but not the original source.
+    IoError {
+        /// the error returned by the writer
+        source: io::Error,
+        /// the number of bytes written successfully before the error
+        bytes_written: usize,
+    },
+    /// text written by a number formatter which is not a JSON number
+    BadNumber(string::String),
+    /// error reported by an `Encodable` implementation
+    ApplicationError(string::String),
 }
 
 impl PartialEq for EncoderError {
     fn eq(&self, other: &EncoderError) -> bool {
-        match (*self, *other) {
-            (EncoderError::FmtError(_), EncoderError::FmtError(_)) => true,
-            (EncoderError::BadHashmapKey, EncoderError::BadHashmapKey) => true,
+        match (self, other) {
+            (&EncoderError::FmtError(_), &EncoderError::FmtError(_)) => true,
+            (&EncoderError::BadHashmapKey, &EncoderError::BadHashmapKey) => true,
+            (&EncoderError::BadNumber(ref a), &EncoderError::BadNumber(ref b)) => a == b,
+            (&EncoderError::ApplicationError(ref a),
+             &EncoderError::ApplicationError(ref b)) => a == b,
             _ => false,
         }
     }
 }
 
 impl Clone for EncoderError {
-    fn clone(&self) -> Self { *self }
+    fn clone(&self) -> Self {
+        match *self {
+            EncoderError::FmtError(e) => EncoderError::FmtError(e),
+            EncoderError::BadHashmapKey => EncoderError::BadHashmapKey,
+            EncoderError::IoError { ref source, bytes_written } => EncoderError::IoError {
+                source: io::Error::new(source.kind(), source.to_string()),
+                bytes_written: bytes_written,
+            },
+            EncoderError::BadNumber(ref s) => EncoderError::BadNumber(s.clone()),
+            EncoderError::ApplicationError(ref s) => {
+                EncoderError::ApplicationError(s.clone())
+            }
+        }
+    }
 }
 
 /// Returns a readable error string for a given error code.
@@ -360,7 +526,10 @@ pub fn error_str(error: ErrorCode) -> &'static str {
         EOFWhileParsingArray => "EOF While parsing array",
         EOFWhileParsingValue => "EOF While parsing value",
         EOFWhileParsingString => "EOF While parsing string",
+        EOFWhileParsingEscape => "EOF While parsing escape",
+        EOFWhileParsingComment => "EOF While parsing comment",
         KeyMustBeAString => "key must be a string",
+        DuplicateKey => "duplicate key",
         ExpectedColon => "expected `:`",
         TrailingCharacters => "trailing characters",
         TrailingComma => "trailing comma",
@@ -372,6 +541,7 @@ pub fn error_str(error: ErrorCode) -> &'static str {
         InvalidUnicodeCodePoint => "invalid Unicode code point",
         LoneLeadingSurrogateInHexEscape => "lone leading surrogate in hex escape",
         UnexpectedEndOfHexEscape => "unexpected end of hex escape",
+        RecursionLimitExceeded => "recursion limit exceeded",
     }
 }
 
@@ -396,6 +566,203 @@ pub fn encode<T: ::Encodable>(object: &T) -> EncodeResult<string::String> {
     Ok(s)
 }
 
+/// Shortcut function to encode a `T` into the bytes of a JSON document
+pub fn encode_to_vec<T: ::Encodable>(object: &T) -> EncodeResult<Vec<u8>> {
+    let mut v = Vec::new();
+    try!(IoEncoder::new(&mut v).encode(object));
+    Ok(v)
+}
+
+/// Shortcut function to encode a `T` as JSON directly into an `io::Write`
+///
+/// If writing fails, the returned `EncoderError::IoError` records how many
+/// bytes were written successfully before the failure.
+pub fn to_writer<W: io::Write, T: ::Encodable>(writer: &mut W,
+                                               object: &T) -> EncodeResult<()> {
+    IoEncoder::new(writer).encode(object)
+}
+
+/// Shortcut function to encode a `T` as pretty-printed JSON directly into an
+/// `io::Write`
+///
+/// If writing fails, the returned `EncoderError::IoError` records how many
+/// bytes were written successfully before the failure.
+pub fn to_writer_pretty<W: io::Write, T: ::Encodable>(writer: &mut W,
+                                                      object: &T) -> EncodeResult<()> {
+    IoEncoder::new_pretty(writer).encode(object)
+}
+
+/// Reads a JSON array from `rdr` one element at a time, and writes the array
+/// of the results of `f` applied to each element to `wtr` in compact JSON.
+/// Only one element is held in memory at a time, so arrays larger than the
+/// available memory can be transformed.
+///
+/// Errors reading from `rdr` or writing to `wtr` are returned as `IoError`,
+/// and any other error encoding the output as `EncodeError`. Any output
+/// written before an error should be discarded.
+pub fn transform_array<R, W, F>(rdr: R, mut wtr: W, mut f: F) -> Result<(), ParserError>
+    where R: io::Read, W: io::Write, F: FnMut(Json) -> Json
+{
+    fn write_all<W: io::Write>(wtr: &mut W, s: &str) -> Result<(), ParserError> {
+        wtr.write_all(s.as_bytes()).map_err(IoError)
+    }
+
+    fn transform<T, W, F>(builder: &mut Builder<T>, wtr: &mut W, f: &mut F)
+                          -> Result<(), ParserError>
+        where T: Iterator<Item = char>, W: io::Write, F: FnMut(Json) -> Json
+    {
+        builder.bump();
+        match builder.token.take() {
+            Some(ArrayStart) => {}
+            Some(Error(e)) => return Err(e),
+            None => return builder.parser.error(EOFWhileParsingValue),
+            Some(_) => return builder.parser.error(InvalidSyntax),
+        }
+        try!(write_all(wtr, "["));
+        builder.bump();
+        let mut first = true;
+        loop {
+            if let Some(ArrayEnd) = builder.token {
+                break;
+            }
+            let value = try!(builder.build_value());
+            if !first {
+                try!(write_all(wtr, ","));
+            }
+            first = false;
+            try!(to_writer(wtr, &f(value)));
+            builder.bump();
+        }
+        try!(write_all(wtr, "]"));
+        builder.bump();
+        match builder.token.take() {
+            None => {}
+            Some(Error(e)) => return Err(e),
+            Some(_) => return builder.parser.error(InvalidSyntax),
+        }
+        wtr.flush().map_err(IoError)
+    }
+
+    let bytes = ReadBytes { bytes: io::BufReader::new(rdr).bytes(), error: None };
+    let mut builder = Builder::new(Utf8Chars { bytes: bytes, invalid: false });
+    let result = transform(&mut builder, &mut wtr, &mut f);
+    // Both an error reading and invalid UTF-8 end the input early, which
+    // leaves the real cause behind whichever error was reported for that.
+    if let Some(e) = builder.parser.rdr.bytes.error.take() {
+        return Err(IoError(e));
+    }
+    if builder.parser.rdr.invalid {
+        return builder.parser.error(NotUtf8);
+    }
+    result
+}
+
+/// A structure for serializing values to JSON directly into an `io::Write`,
+/// which it owns.
+pub struct IoEncoder<W> {
+    writer: W,
+    written: usize,
+    pretty: bool,
+}
+
+impl<W: io::Write> IoEncoder<W> {
+    /// Creates a new encoder whose output will be written in compact JSON to
+    /// the specified writer
+    pub fn new(writer: W) -> IoEncoder<W> {
+        IoEncoder { writer: writer, written: 0, pretty: false }
+    }
+
+    /// Creates a new encoder whose output will be written in human-readable
+    /// JSON to the specified writer
+    pub fn new_pretty(writer: W) -> IoEncoder<W> {
+        IoEncoder { writer: writer, written: 0, pretty: true }
+    }
+
+    /// Encodes `object` to the writer. Values encoded by successive calls are
+    /// written one after another.
+    ///
+    /// If writing fails, the returned `EncoderError::IoError` records how many
+    /// bytes this encoder wrote successfully before the failure.
+    pub fn encode<T: ::Encodable>(&mut self, object: &T) -> EncodeResult<()> {
+        let mut adapter = IoWriter::new(&mut self.writer, self.written);
+        let result = {
+            let mut encoder = if self.pretty {
+                Encoder::new_pretty(&mut adapter)
+            } else {
+                Encoder::new(&mut adapter)
+            };
+            object.encode(&mut encoder)
+        };
+        self.written = adapter.written;
+        adapter.check(result)
+    }
+
+    /// Flushes the writer, making sure all output has reached its
+    /// destination, and drops the encoder.
+    pub fn finish(mut self) -> EncodeResult<()> {
+        match self.writer.flush() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(EncoderError::IoError { source: e, bytes_written: self.written }),
+        }
+    }
+
+    /// Unwraps this encoder, returning the underlying writer without flushing
+    /// it.
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+/// Adapts an `io::Write` for use by an `Encoder`, keeping count of the bytes
+/// written and the io error which stopped the encoder, if any.
+struct IoWriter<'a, W: 'a + ?Sized> {
+    inner: &'a mut W,
+    written: usize,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write + ?Sized> IoWriter<'a, W> {
+    fn new(inner: &'a mut W, written: usize) -> IoWriter<'a, W> {
+        IoWriter { inner: inner, written: written, error: None }
+    }
+
+    /// Replaces the formatting error reported for a failed write with the
+    /// underlying io error.
+    fn check<T>(&mut self, result: EncodeResult<T>) -> EncodeResult<T> {
+        match (result, self.error.take()) {
+            (Err(EncoderError::FmtError(_)), Some(e)) => {
+                Err(EncoderError::IoError { source: e, bytes_written: self.written })
+            }
+            (result, _) => result,
+        }
+    }
+}
+
+impl<'a, W: io::Write + ?Sized> fmt::Write for IoWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut buf = s.as_bytes();
+        while !buf.is_empty() {
+            match self.inner.write(buf) {
+                Ok(0) => {
+                    self.error = Some(io::Error::new(io::ErrorKind::WriteZero,
+                                                     "failed to write whole buffer"));
+                    return Err(fmt::Error);
+                }
+                Ok(n) => {
+                    self.written += n;
+                    buf = &buf[n..];
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.error = Some(e);
+                    return Err(fmt::Error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for ErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         error_str(*self).fmt(f)
@@ -414,7 +781,34 @@ impl StdError for DecoderError {
 
 impl fmt::Display for DecoderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&self, f)
+        match *self {
+            UnexpectedVariantError(ref got, ref expected) => {
+                try!(write!(f, "unknown variant {:?}, expected one of ", got));
+                for (i, name) in expected.iter().enumerate() {
+                    if i != 0 { try!(write!(f, ", ")); }
+                    try!(write!(f, "{:?}", name));
+                }
+                Ok(())
+            }
+            _ => fmt::Debug::fmt(&self, f),
+        }
+    }
+}
+
+impl StdError for SchemaError {
+    fn description(&self) -> &str { "schema violation" }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaError::TypeMismatch(ref ptr, expected, found) => {
+                write!(f, "{:?}: expected {}, found {}", ptr, expected, found)
+            }
+            SchemaError::MissingKey(ref ptr, ref key) => {
+                write!(f, "{:?}: missing required key {:?}", ptr, key)
+            }
+        }
     }
 }
 
@@ -430,7 +824,13 @@ impl StdError for ParserError {
 
 impl fmt::Display for ParserError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&self, f)
+        match *self {
+            SyntaxError(code, line, col) | SyntaxErrorAt(code, line, col, _) => {
+                write!(f, "{} at line {} column {}", error_str(code), line, col)
+            }
+            IoError(ref e) => fmt::Display::fmt(e, f),
+            EncodeError(ref e) => fmt::Display::fmt(e, f),
+        }
     }
 }
 
@@ -440,8 +840,23 @@ impl From<io::Error> for ParserError {
     }
 }
 
+impl From<EncoderError> for ParserError {
+    fn from(err: EncoderError) -> ParserError {
+        match err {
+            EncoderError::IoError { source, .. } => IoError(source),
+            err => EncodeError(err),
+        }
+    }
+}
+
 impl StdError for EncoderError {
     fn description(&self) -> &str { "encoder error" }
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            EncoderError::IoError { ref source, .. } => Some(source),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for EncoderError {
@@ -457,7 +872,15 @@ impl From<fmt::Error> for EncoderError {
 pub type EncodeResult<T> = Result<T, EncoderError>;
 pub type DecodeResult<T> = Result<T, DecoderError>;
 
-fn escape_str(wr: &mut fmt::Write, v: &str) -> EncodeResult<()> {
+// The characters which `escape_str` escapes in addition to those which JSON
+// requires to be escaped.
+#[derive(Clone, Copy, Default)]
+struct Escapes {
+    ascii_only: bool,
+    solidus: bool,
+}
+
+fn escape_str(wr: &mut fmt::Write, v: &str, escapes: Escapes) -> EncodeResult<()> {
     try!(wr.write_str("\""));
 
     let mut start = 0;
@@ -466,6 +889,7 @@ fn escape_str(wr: &mut fmt::Write, v: &str) -> EncodeResult<()> {
         let escaped = match byte {
             b'"' => "\\\"",
             b'\\' => "\\\\",
+            b'/' if escapes.solidus => "\\/",
             b'\x00' => "\\u0000",
             b'\x01' => "\\u0001",
             b'\x02' => "\\u0002",
@@ -499,6 +923,18 @@ fn escape_str(wr: &mut fmt::Write, v: &str) -> EncodeResult<()> {
             b'\x1e' => "\\u001e",
             b'\x1f' => "\\u001f",
             b'\x7f' => "\\u007f",
+            0x80...0xff if escapes.ascii_only => {
+                // The continuation bytes of a character were escaped along
+                // with its first byte.
+                if byte < 0xc0 { continue; }
+                if start < i {
+                    try!(wr.write_str(&v[start..i]));
+                }
+                let c = v[i..].chars().next().unwrap();
+                try!(escape_unicode_scalar(wr, c));
+                start = i + c.len_utf8();
+                continue;
+            }
             _ => { continue; }
         };
 
@@ -519,14 +955,50 @@ fn escape_str(wr: &mut fmt::Write, v: &str) -> EncodeResult<()> {
     Ok(())
 }
 
-fn escape_char(writer: &mut fmt::Write, v: char) -> EncodeResult<()> {
+// Writes an object key, unquoted if it is an identifier and `json5` is set.
+fn escape_key(wr: &mut fmt::Write, v: &str, json5: bool, escapes: Escapes) -> EncodeResult<()> {
+    let mut chars = v.chars();
+    let is_identifier = chars.next().map_or(false, |c| {
+        c.is_ascii_alphabetic() || c == '_' || c == '$'
+    }) && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if json5 && is_identifier {
+        try!(wr.write_str(v));
+        Ok(())
+    } else {
+        escape_str(wr, v, escapes)
+    }
+}
+
+fn escape_char(writer: &mut fmt::Write, v: char, escapes: Escapes) -> EncodeResult<()> {
     let mut buf = [0; 4];
     let _ = write!(&mut &mut buf[..], "{}", v);
     let buf = unsafe { str::from_utf8_unchecked(&buf[..v.len_utf8()]) };
-    escape_str(writer, buf)
+    escape_str(writer, buf, escapes)
 }
 
-fn spaces(wr: &mut fmt::Write, n: u32) -> EncodeResult<()> {
+/// Writes `c` as a JSON `\uXXXX` escape, or as a `\uXXXX\uXXXX` UTF-16
+/// surrogate pair if it lies outside the Basic Multilingual Plane.
+pub fn escape_unicode_scalar(wr: &mut fmt::Write, c: char) -> EncodeResult<()> {
+    let n = c as u32;
+    if n < 0x1_0000 {
+        try!(write!(wr, "\\u{:04x}", n));
+    } else {
+        let n = n - 0x1_0000;
+        let n1 = 0xD800 | (n >> 10);
+        let n2 = 0xDC00 | (n & 0x3FF);
+        try!(write!(wr, "\\u{:04x}\\u{:04x}", n1, n2));
+    }
+    Ok(())
+}
+
+// Writes `n` copies of `unit`, or `n` spaces if there is no `unit`.
+fn write_indent(wr: &mut fmt::Write, n: u32, unit: Option<&str>) -> EncodeResult<()> {
+    if let Some(unit) = unit {
+        for _ in 0..n {
+            try!(wr.write_str(unit));
+        }
+        return Ok(());
+    }
     let mut n = n as usize;
     const BUF: &'static str = "                ";
 
@@ -541,6 +1013,44 @@ fn spaces(wr: &mut fmt::Write, n: u32) -> EncodeResult<()> {
     Ok(())
 }
 
+/// How an `Encoder` writes finite floating point numbers.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FloatFormat {
+    /// The fewest digits which read back as the same value, such as `0.1`
+    /// and `1e20`
+    Shortest,
+    /// The given number of digits after the decimal point, such as `0.100`
+    Fixed(usize),
+    /// The given number of significant digits, such as `0.100000`, or
+    /// `1.00000e20` for numbers whose exponent is less than -4 or at least
+    /// the number of digits
+    Significant(usize),
+}
+
+impl Default for FloatFormat {
+    fn default() -> FloatFormat { FloatFormat::Shortest }
+}
+
+// Formats a finite `v` according to `format`.
+fn fmt_float(v: f64, format: FloatFormat) -> string::String {
+    match format {
+        FloatFormat::Shortest => fmt_number_or_null(v),
+        FloatFormat::Fixed(digits) => format!("{:.*}", digits, v),
+        FloatFormat::Significant(digits) => {
+            let digits = cmp::max(digits, 1);
+            // Rounding to the digits may change the exponent, so take it
+            // from the rounded number.
+            let sci = format!("{:.*e}", digits - 1, v);
+            let exp: i32 = sci[sci.find('e').unwrap() + 1..].parse().unwrap();
+            if exp < -4 || exp >= digits as i32 {
+                sci
+            } else {
+                format!("{:.*}", (digits as i32 - 1 - exp) as usize, v)
+            }
+        }
+    }
+}
+
 fn fmt_number_or_null(v: f64) -> string::String {
     use std::num::FpCategory::{Nan, Infinite};
 
@@ -553,18 +1063,123 @@ fn fmt_number_or_null(v: f64) -> string::String {
     }
 }
 
+fn write_canonical(json: &Json, out: &mut Vec<u8>) {
+    fn write_str(v: &str, out: &mut Vec<u8>) {
+        out.push(b'"');
+        for byte in v.bytes() {
+            match byte {
+                b'"' => out.extend_from_slice(b"\\\""),
+                b'\\' => out.extend_from_slice(b"\\\\"),
+                b'\x08' => out.extend_from_slice(b"\\b"),
+                b'\t' => out.extend_from_slice(b"\\t"),
+                b'\n' => out.extend_from_slice(b"\\n"),
+                b'\x0c' => out.extend_from_slice(b"\\f"),
+                b'\r' => out.extend_from_slice(b"\\r"),
+                b'\x00' ... b'\x1f' => {
+                    out.extend_from_slice(format!("\\u{:04x}", byte).as_bytes())
+                }
+                _ => out.push(byte),
+            }
+        }
+        out.push(b'"');
+    }
+
+    match *json {
+        Json::I64(n) => out.extend_from_slice(n.to_string().as_bytes()),
+        Json::U64(n) => out.extend_from_slice(n.to_string().as_bytes()),
+        Json::F64(f) => out.extend_from_slice(fmt_number_canonical(f).as_bytes()),
+        Json::String(ref s) => write_str(s, out),
+        Json::Boolean(b) => out.extend_from_slice(if b { b"true" } else { b"false" }),
+        Json::Array(ref array) => {
+            out.push(b'[');
+            for (i, v) in array.iter().enumerate() {
+                if i != 0 { out.push(b','); }
+                write_canonical(v, out);
+            }
+            out.push(b']');
+        }
+        Json::Object(ref obj) => {
+            let mut entries: Vec<_> = obj.iter().collect();
+            entries.sort_by(|&(a, _), &(b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push(b'{');
+            for (i, &(k, v)) in entries.iter().enumerate() {
+                if i != 0 { out.push(b','); }
+                write_str(k, out);
+                out.push(b':');
+                write_canonical(v, out);
+            }
+            out.push(b'}');
+        }
+        Json::Null => out.extend_from_slice(b"null"),
+    }
+}
+
+/// Formats a float the way ECMAScript's `Number.prototype.toString` does,
+/// as required by RFC 8785.
+fn fmt_number_canonical(v: f64) -> string::String {
+    if !v.is_finite() {
+        return "null".to_string();
+    }
+    if v == 0.0 {
+        return "0".to_string();
+    }
+
+    // Shortest round-tripping digits and the exponent of the first of them
+    let sci = format!("{:e}", v.abs());
+    let (mantissa, exp) = sci.split_at(sci.find('e').unwrap());
+    let digits: string::String = mantissa.chars().filter(|&c| c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exp[1..].parse::<i32>().unwrap() + 1;
+
+    let mut s = if v < 0.0 { "-".to_string() } else { string::String::new() };
+    if k <= n && n <= 21 {
+        s.push_str(&digits);
+        for _ in 0..n - k { s.push('0'); }
+    } else if 0 < n && n <= 21 {
+        s.push_str(&digits[..n as usize]);
+        s.push('.');
+        s.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        s.push_str("0.");
+        for _ in 0..-n { s.push('0'); }
+        s.push_str(&digits);
+    } else {
+        s.push_str(&digits[..1]);
+        if k > 1 {
+            s.push('.');
+            s.push_str(&digits[1..]);
+        }
+        s.push_str(&format!("e{}{}", if n - 1 < 0 { '-' } else { '+' }, (n - 1).abs()));
+    }
+    s
+}
+
 macro_rules! emit_enquoted_if_mapkey {
     ($enc:ident,$e:expr) => {
         if $enc.is_emitting_map_key {
-            try!(write!($enc.writer, "\"{}\"", $e));
-            Ok(())
+            if $enc.reorders_maps() {
+                $enc.map_key = $e.to_string();
+            }
+            $enc.paint(COLOR_KEY, |enc| {
+                try!(write!(enc.writer, "\"{}\"", $e));
+                Ok(())
+            })
         } else {
-            try!(write!($enc.writer, "{}", $e));
-            Ok(())
+            $enc.paint(COLOR_NUMBER, |enc| {
+                try!(write!(enc.writer, "{}", $e));
+                Ok(())
+            })
         }
     }
 }
 
+// ANSI escape sequences used by colored output
+const COLOR_KEY: &'static str = "\x1b[34m";
+const COLOR_STRING: &'static str = "\x1b[32m";
+const COLOR_NUMBER: &'static str = "\x1b[36m";
+const COLOR_LITERAL: &'static str = "\x1b[35m";
+const COLOR_RESET: &'static str = "\x1b[0m";
+
 enum EncodingFormat {
     Compact,
     Pretty {
@@ -573,11 +1188,45 @@ enum EncodingFormat {
     }
 }
 
+/// The output of an `Encoder`, which is redirected into the innermost
+/// buffer while the entries of an object are collected for reordering.
+struct EncoderWriter<'a> {
+    inner: &'a mut (fmt::Write+'a),
+    buffers: Vec<string::String>,
+}
+
+impl<'a> fmt::Write for EncoderWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.buffers.last_mut() {
+            Some(buf) => { buf.push_str(s); Ok(()) }
+            None => self.inner.write_str(s),
+        }
+    }
+}
+
 /// A structure for implementing serialization to JSON.
 pub struct Encoder<'a> {
-    writer: &'a mut (fmt::Write+'a),
+    writer: EncoderWriter<'a>,
     format : EncodingFormat,
+    // The string written per level of indentation, instead of `indent` spaces.
+    indent_str: Option<&'a str>,
     is_emitting_map_key: bool,
+    // the text of the last map key emitted, before quoting and escaping
+    map_key: string::String,
+    colored: bool,
+    json5: bool,
+    float_format: FloatFormat,
+    float_formatter: Option<&'a Fn(f64, &mut fmt::Write) -> fmt::Result>,
+    escapes: Escapes,
+    allow_nonfinite: bool,
+    // comments waiting to be written before the next element
+    comments: Vec<string::String>,
+    sort_keys: bool,
+    key_order: Vec<string::String>,
+    align_values: bool,
+    // (key, encoded key, encoded value) of the entries of the objects being
+    // reordered or aligned
+    entries: Vec<Vec<(string::String, string::String, string::String)>>,
 }
 
 impl<'a> Encoder<'a> {
@@ -585,12 +1234,25 @@ impl<'a> Encoder<'a> {
     /// JSON to the specified writer
     pub fn new_pretty(writer: &'a mut fmt::Write) -> Encoder<'a> {
         Encoder {
-            writer: writer,
+            writer: EncoderWriter { inner: writer, buffers: Vec::new() },
             format: EncodingFormat::Pretty {
                 curr_indent: 0,
                 indent: 2,
             },
+            indent_str: None,
             is_emitting_map_key: false,
+            map_key: string::String::new(),
+            colored: false,
+            json5: false,
+            float_format: FloatFormat::Shortest,
+            float_formatter: None,
+            escapes: Escapes::default(),
+            allow_nonfinite: false,
+            comments: Vec::new(),
+            sort_keys: false,
+            key_order: Vec::new(),
+            align_values: false,
+            entries: Vec::new(),
         }
     }
 
@@ -598,9 +1260,22 @@ impl<'a> Encoder<'a> {
     /// JSON to the specified writer
     pub fn new(writer: &'a mut fmt::Write) -> Encoder<'a> {
         Encoder {
-            writer: writer,
+            writer: EncoderWriter { inner: writer, buffers: Vec::new() },
             format: EncodingFormat::Compact,
+            indent_str: None,
             is_emitting_map_key: false,
+            map_key: string::String::new(),
+            colored: false,
+            json5: false,
+            float_format: FloatFormat::Shortest,
+            float_formatter: None,
+            escapes: Escapes::default(),
+            allow_nonfinite: false,
+            comments: Vec::new(),
+            sort_keys: false,
+            key_order: Vec::new(),
+            align_values: false,
+            entries: Vec::new(),
         }
     }
 
@@ -612,11 +1287,278 @@ impl<'a> Encoder<'a> {
             let level = curr_indent.checked_div(*indent).unwrap_or(0);
             *indent = new_indent;
             *curr_indent = level * *indent;
+            self.indent_str = None;
             Ok(())
         } else {
             Err(())
         }
     }
+
+    /// Indent each level with a copy of `indent_str`, such as `"\t"`, rather
+    /// than with spaces. This is safe to set during encoding.
+    pub fn set_indent_str(&mut self, indent_str: &'a str) -> Result<(), ()> {
+        // One column per level, each of which is written as `indent_str`
+        try!(self.set_indent(1));
+        self.indent_str = Some(indent_str);
+        Ok(())
+    }
+
+    /// Emit JSON5 rather than JSON: object keys which are identifiers are
+    /// written without quotes and, when pretty printing, every non-empty
+    /// array and object gets a trailing comma. This is meant for generating
+    /// configuration files which people will edit by hand.
+    pub fn set_json5(&mut self, json5: bool) {
+        self.json5 = json5;
+    }
+
+    /// Writes `text` as a `//` comment on its own line, indented like the
+    /// element which follows it. Inside an array or object the comment is
+    /// held back until the next element, or the closing bracket, so that it
+    /// never swallows a separating comma. Comments are only written when
+    /// pretty printing JSON5; otherwise this does nothing.
+    pub fn emit_comment(&mut self, text: &str) -> EncodeResult<()> {
+        let curr_indent = match self.format {
+            EncodingFormat::Pretty{curr_indent, ..} if self.json5 => curr_indent,
+            _ => return Ok(()),
+        };
+        if curr_indent == 0 {
+            for line in text.lines() {
+                try!(write!(self.writer, "// {}\n", line));
+            }
+        } else {
+            self.comments.push(text.to_string());
+        }
+        Ok(())
+    }
+
+    /// Write finite `f64` and `f32` values in the given format, rather than
+    /// in the shortest form which reads back as the same value. Non-finite
+    /// values are still written as `null`.
+    ///
+    /// This replaces any formatter given to `set_float_formatter`.
+    pub fn set_float_format(&mut self, format: FloatFormat) {
+        self.float_format = format;
+        self.float_formatter = None;
+    }
+
+    /// Write finite `f64` and `f32` values with `formatter` rather than in
+    /// the shortest form which reads back as the same value, for example to
+    /// round them to a fixed number of decimal places. Non-finite values are
+    /// still written as `null`. Encoding fails with `BadNumber` if the
+    /// formatter writes anything other than a JSON number.
+    ///
+    /// The formatter takes the place of any format given to
+    /// `set_float_format`, until that is called again.
+    pub fn set_float_formatter(&mut self,
+                               formatter: &'a Fn(f64, &mut fmt::Write) -> fmt::Result) {
+        self.float_formatter = Some(formatter);
+    }
+
+    /// Escape every non-ASCII character in strings and object keys as
+    /// `\uXXXX`, using a UTF-16 surrogate pair for characters outside the
+    /// Basic Multilingual Plane, so that the output is plain ASCII.
+    pub fn set_ascii_only(&mut self, ascii_only: bool) {
+        self.escapes.ascii_only = ascii_only;
+    }
+
+    /// Escape `/` as `\/` in strings and object keys, so that the output can
+    /// be embedded in an HTML `<script>` element without `</` closing it.
+    pub fn set_escape_solidus(&mut self, escape_solidus: bool) {
+        self.escapes.solidus = escape_solidus;
+    }
+
+    /// Emit `NaN`, `Infinity` and `-Infinity` for non-finite floats instead
+    /// of `null`, so that they survive a round trip through a `Parser` with
+    /// `ParserConfig::allow_nonfinite` set. The output is not valid JSON.
+    pub fn set_allow_nonfinite(&mut self, allow_nonfinite: bool) {
+        self.allow_nonfinite = allow_nonfinite;
+    }
+
+    /// Emit the entries of every map sorted by their keys, so that
+    /// maps without an inherent order, such as `HashMap`, are written the
+    /// same way on every run. Each entry of a map is encoded into a buffer
+    /// of its own, and written once the whole map has been encoded, which
+    /// costs an allocation per key and per value. Struct fields are not
+    /// reordered.
+    pub fn set_sort_keys(&mut self, sort_keys: bool) {
+        self.sort_keys = sort_keys;
+    }
+
+    /// Emit the keys of every object in the given order, followed by the
+    /// remaining keys in alphabetical order, instead of in the order they
+    /// are encoded. The entries of each object are buffered until the whole
+    /// object has been encoded.
+    pub fn set_key_order(&mut self, keys: &[&str]) {
+        self.key_order = keys.iter().map(|k| k.to_string()).collect();
+    }
+
+    /// When pretty-printing, pad the keys of each object to the width of its
+    /// longest key, so that all of the object's values start in the same
+    /// column. The entries of each object are buffered until the whole object
+    /// has been encoded. This has no effect on compact output.
+    pub fn set_align_values(&mut self, align: bool) {
+        self.align_values = align;
+    }
+
+    // Whether the entries of maps are buffered to be reordered.
+    fn reorders_maps(&self) -> bool {
+        self.sort_keys || !self.key_order.is_empty()
+    }
+
+    fn buffers_maps(&self) -> bool {
+        self.reorders_maps() || self.aligns_values()
+    }
+
+    // Whether the entries of objects are buffered to be aligned.
+    fn aligns_values(&self) -> bool {
+        match self.format {
+            EncodingFormat::Pretty{..} => self.align_values,
+            EncodingFormat::Compact => false,
+        }
+    }
+
+    /// Writes the buffered entries of an object, sorted by key with priority
+    /// keys first if `reorder` is set, padding the keys if values are aligned.
+    fn write_ordered_entries(&mut self,
+                             mut entries: Vec<(string::String, string::String, string::String)>,
+                             reorder: bool)
+                             -> EncodeResult<()> {
+        if reorder {
+            let key_order = &self.key_order;
+            let rank = |key: &str| {
+                key_order.iter().position(|k| *k == key).unwrap_or(key_order.len())
+            };
+            entries.sort_by(|a, b| (rank(&a.0), &a.0).cmp(&(rank(&b.0), &b.0)));
+        }
+        let width = if self.aligns_values() {
+            entries.iter().map(|e| e.1.chars().count()).max().unwrap_or(0)
+        } else {
+            0
+        };
+
+        for (idx, (_, key, value)) in entries.into_iter().enumerate() {
+            if idx != 0 {
+                try!(write!(self.writer, ","));
+            }
+            if let EncodingFormat::Pretty{curr_indent, ..} = self.format {
+                try!(write!(self.writer, "\n"));
+                try!(write_indent(&mut self.writer, curr_indent, self.indent_str));
+            }
+            try!(self.writer.write_str(&key));
+            if let EncodingFormat::Pretty{..} = self.format {
+                try!(write!(self.writer, ": "));
+                for _ in key.chars().count()..width {
+                    try!(self.writer.write_str(" "));
+                }
+            } else {
+                try!(write!(self.writer, ":"));
+            }
+            try!(self.writer.write_str(&value));
+        }
+        Ok(())
+    }
+
+    /// Emit the given text as a bare JSON number. This allows numeric types
+    /// which are not representable as a `f64`, such as decimals or bignums,
+    /// to be encoded losslessly from their textual form.
+    ///
+    /// The text is written as is and must be a valid JSON number.
+    pub fn emit_number_str(&mut self, v: &str) -> EncodeResult<()> {
+        debug_assert!(is_number_str(v), "not a valid JSON number: {:?}", v);
+        emit_enquoted_if_mapkey!(self, v)
+    }
+
+    /// Writes the token emitted by `f` wrapped in the given ANSI color when
+    /// colored output is enabled, or as is otherwise.
+    fn paint<F>(&mut self, color: &str, f: F) -> EncodeResult<()> where
+        F: FnOnce(&mut Encoder<'a>) -> EncodeResult<()>,
+    {
+        if !(cfg!(feature = "color") && self.colored) {
+            return f(self);
+        }
+        try!(self.writer.write_str(color));
+        try!(f(self));
+        try!(self.writer.write_str(COLOR_RESET));
+        Ok(())
+    }
+
+    /// Writes a string token, colored as an object key when one is expected.
+    fn paint_str(&mut self, v: &str) -> EncodeResult<()> {
+        if self.is_emitting_map_key {
+            if self.reorders_maps() {
+                self.map_key = v.to_string();
+            }
+            return self.paint(COLOR_KEY, |enc| escape_key(&mut enc.writer, v, enc.json5, enc.escapes));
+        }
+        self.paint(COLOR_STRING, |enc| escape_str(&mut enc.writer, v, enc.escapes))
+    }
+
+    /// Writes the comments passed to `emit_comment` since the last element,
+    /// each line on a line of its own.
+    fn write_comments(&mut self) -> EncodeResult<()> {
+        if self.comments.is_empty() {
+            return Ok(());
+        }
+        let mut comments = Vec::new();
+        swap(&mut comments, &mut self.comments);
+        if let EncodingFormat::Pretty{curr_indent, ..} = self.format {
+            for line in comments.iter().flat_map(|c| c.lines()) {
+                try!(write!(self.writer, "\n"));
+                try!(write_indent(&mut self.writer, curr_indent, self.indent_str));
+                try!(write!(self.writer, "// {}", line));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the comma JSON5 allows after the last element of a pretty
+    /// printed array or object.
+    fn trailing_comma(&mut self) -> EncodeResult<()> {
+        if let EncodingFormat::Pretty{..} = self.format {
+            if self.json5 {
+                try!(write!(self.writer, ","));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replaces the `${name}` placeholders in `s`, recording the name of the first
+// one which cannot be resolved in `missing`.
+fn interpolate_str<F>(s: &str, resolve: &F, missing: &mut Option<string::String>)
+                      -> string::String
+    where F: Fn(&str) -> Option<string::String>
+{
+    let mut out = string::String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        let name = &rest[start + 2..end];
+        match resolve(name) {
+            Some(value) => out.push_str(&value),
+            None => {
+                if missing.is_none() {
+                    *missing = Some(name.to_string());
+                }
+                out.push_str(&rest[start..end + 1]);
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn is_number_str(v: &str) -> bool {
+    let mut parser = Parser::new(v.chars());
+    match parser.next() {
+        Some(I64Value(_)) | Some(U64Value(_)) | Some(F64Value(_)) => parser.next().is_none(),
+        _ => false,
+    }
 }
 
 impl<'a> ::Encoder for Encoder<'a> {
@@ -624,17 +1566,33 @@ impl<'a> ::Encoder for Encoder<'a> {
 
     fn emit_nil(&mut self) -> EncodeResult<()> {
         if self.is_emitting_map_key { return Err(EncoderError::BadHashmapKey); }
-        try!(write!(self.writer, "null"));
-        Ok(())
+        self.paint(COLOR_LITERAL, |enc| {
+            try!(write!(enc.writer, "null"));
+            Ok(())
+        })
     }
 
     fn emit_usize(&mut self, v: usize) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
+    fn emit_u128(&mut self, v: u128) -> EncodeResult<()> {
+        // The parser only reads integers which fit in 64 bits, so larger
+        // values are written as strings, which `read_u128` also accepts.
+        if v > u64::MAX as u128 {
+            return self.emit_str(&v.to_string());
+        }
+        emit_enquoted_if_mapkey!(self, v)
+    }
     fn emit_u64(&mut self, v: u64) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
     fn emit_u32(&mut self, v: u32) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
     fn emit_u16(&mut self, v: u16) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
     fn emit_u8(&mut self, v: u8) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
 
     fn emit_isize(&mut self, v: isize) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
+    fn emit_i128(&mut self, v: i128) -> EncodeResult<()> {
+        if v < i64::MIN as i128 || v > u64::MAX as i128 {
+            return self.emit_str(&v.to_string());
+        }
+        emit_enquoted_if_mapkey!(self, v)
+    }
     fn emit_i64(&mut self, v: i64) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
     fn emit_i32(&mut self, v: i32) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
     fn emit_i16(&mut self, v: i16) -> EncodeResult<()> { emit_enquoted_if_mapkey!(self, v) }
@@ -642,15 +1600,43 @@ impl<'a> ::Encoder for Encoder<'a> {
 
     fn emit_bool(&mut self, v: bool) -> EncodeResult<()> {
         if self.is_emitting_map_key { return Err(EncoderError::BadHashmapKey); }
-        if v {
-            try!(write!(self.writer, "true"));
-        } else {
-            try!(write!(self.writer, "false"));
-        }
-        Ok(())
+        self.paint(COLOR_LITERAL, |enc| {
+            if v {
+                try!(write!(enc.writer, "true"));
+            } else {
+                try!(write!(enc.writer, "false"));
+            }
+            Ok(())
+        })
     }
 
     fn emit_f64(&mut self, v: f64) -> EncodeResult<()> {
+        // A non-finite key would be written as the string "null"
+        if self.is_emitting_map_key && !v.is_finite() {
+            return Err(EncoderError::BadHashmapKey);
+        }
+        if self.allow_nonfinite && !v.is_finite() {
+            let literal = if v.is_nan() {
+                "NaN"
+            } else if v > 0.0 {
+                "Infinity"
+            } else {
+                "-Infinity"
+            };
+            return emit_enquoted_if_mapkey!(self, literal);
+        }
+        if let (Some(formatter), true) = (self.float_formatter, v.is_finite()) {
+            let mut s = string::String::new();
+            try!(formatter(v, &mut s));
+            if !is_number_str(&s) {
+                return Err(EncoderError::BadNumber(s));
+            }
+            return emit_enquoted_if_mapkey!(self, s);
+        }
+        if v.is_finite() {
+            let s = fmt_float(v, self.float_format);
+            return emit_enquoted_if_mapkey!(self, s);
+        }
         emit_enquoted_if_mapkey!(self, fmt_number_or_null(v))
     }
     fn emit_f32(&mut self, v: f32) -> EncodeResult<()> {
@@ -658,10 +1644,14 @@ impl<'a> ::Encoder for Encoder<'a> {
     }
 
     fn emit_char(&mut self, v: char) -> EncodeResult<()> {
-        escape_char(self.writer, v)
+        if self.is_emitting_map_key && self.reorders_maps() {
+            self.map_key = v.to_string();
+        }
+        let color = if self.is_emitting_map_key { COLOR_KEY } else { COLOR_STRING };
+        self.paint(color, |enc| escape_char(&mut enc.writer, v, enc.escapes))
     }
     fn emit_str(&mut self, v: &str) -> EncodeResult<()> {
-        escape_str(self.writer, v)
+        self.paint_str(v)
     }
 
     fn emit_enum<F>(&mut self, _name: &str, f: F) -> EncodeResult<()> where
@@ -672,45 +1662,70 @@ impl<'a> ::Encoder for Encoder<'a> {
 
     fn emit_enum_variant<F>(&mut self,
                             name: &str,
-                            _id: usize,
+                            id: usize,
                             cnt: usize,
                             f: F)
                             -> EncodeResult<()> where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult<()>,
+    {
+        self.emit_enum_variant_tagged("variant", "fields", name, id, cnt, f)
+    }
+
+    fn emit_enum_variant_tagged<F>(&mut self,
+                                   variant_key: &str,
+                                   content_key: &str,
+                                   name: &str,
+                                   _id: usize,
+                                   cnt: usize,
+                                   f: F)
+                                   -> EncodeResult<()> where
+        F: FnOnce(&mut Encoder<'a>) -> EncodeResult<()>,
     {
         // enums are encoded as strings or objects
         // Bunny => "Bunny"
         // Kangaroo(34,"William") => {"variant": "Kangaroo", "fields": [34,"William"]}
         if cnt == 0 {
-            escape_str(self.writer, name)
+            self.paint_str(name)
         } else {
             if self.is_emitting_map_key { return Err(EncoderError::BadHashmapKey); }
+            let align = self.aligns_values();
             if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
+                let mut keys = [string::String::new(), string::String::new()];
+                try!(escape_key(&mut keys[0], variant_key, self.json5, self.escapes));
+                try!(escape_key(&mut keys[1], content_key, self.json5, self.escapes));
+                let width = if align { cmp::max(keys[0].len(), keys[1].len()) } else { 0 };
+                let pad = |key: &str| {
+                    let n = width.saturating_sub(key.len());
+                    format!("{}: {:2$}", key, "", n)
+                };
                 try!(write!(self.writer, "{{\n"));
                 *curr_indent += indent;
-                try!(spaces(self.writer, *curr_indent));
-                try!(write!(self.writer, "\"variant\": "));
-                try!(escape_str(self.writer, name));
+                try!(write_indent(&mut self.writer, *curr_indent, self.indent_str));
+                try!(self.writer.write_str(&pad(&keys[0])));
+                try!(escape_str(&mut self.writer, name, self.escapes));
                 try!(write!(self.writer, ",\n"));
-                try!(spaces(self.writer, *curr_indent));
-                try!(write!(self.writer, "\"fields\": [\n"));
+                try!(write_indent(&mut self.writer, *curr_indent, self.indent_str));
+                try!(self.writer.write_str(&pad(&keys[1])));
+                try!(write!(self.writer, "[\n"));
                 *curr_indent += indent;
             } else {
-                try!(write!(self.writer, "{{\"variant\":"));
-                try!(escape_str(self.writer, name));
-                try!(write!(self.writer, ",\"fields\":["));
+                try!(write!(self.writer, "{{"));
+                try!(escape_key(&mut self.writer, variant_key, self.json5, self.escapes));
+                try!(write!(self.writer, ":"));
+                try!(escape_str(&mut self.writer, name, self.escapes));
+                try!(write!(self.writer, ","));
+                try!(escape_key(&mut self.writer, content_key, self.json5, self.escapes));
+                try!(write!(self.writer, ":["));
             }
             try!(f(self));
-            if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
-                *curr_indent -= indent;
-                try!(write!(self.writer, "\n"));
-                try!(spaces(self.writer, *curr_indent));
-                *curr_indent -= indent;
-                try!(write!(self.writer, "]\n"));
-                try!(spaces(self.writer, *curr_indent));
-                try!(write!(self.writer, "}}"));
-            } else {
-                try!(write!(self.writer, "]}}"));
+            for close in &["]", "}"] {
+                try!(self.trailing_comma());
+                if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
+                    *curr_indent -= indent;
+                    try!(write!(self.writer, "\n"));
+                    try!(write_indent(&mut self.writer, *curr_indent, self.indent_str));
+                }
+                try!(self.writer.write_str(close));
             }
             Ok(())
         }
@@ -727,7 +1742,7 @@ impl<'a> ::Encoder for Encoder<'a> {
             }
         }
         if let EncodingFormat::Pretty{curr_indent, ..} = self.format {
-            try!(spaces(self.writer, curr_indent));
+            try!(write_indent(&mut self.writer, curr_indent, self.indent_str));
         }
         f(self)
     }
@@ -765,11 +1780,20 @@ impl<'a> ::Encoder for Encoder<'a> {
             if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
                 *curr_indent += indent;
             }
-            try!(f(self));
+            if !self.aligns_values() {
+                try!(f(self));
+            } else {
+                self.entries.push(Vec::new());
+                try!(f(self));
+                let entries = self.entries.pop().unwrap();
+                try!(self.write_ordered_entries(entries, false));
+            }
+            try!(self.trailing_comma());
+            try!(self.write_comments());
             if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
                 *curr_indent -= indent;
                 try!(write!(self.writer, "\n"));
-                try!(spaces(self.writer, *curr_indent));
+                try!(write_indent(&mut self.writer, *curr_indent, self.indent_str));
             }
             try!(write!(self.writer, "}}"));
         }
@@ -780,14 +1804,27 @@ impl<'a> ::Encoder for Encoder<'a> {
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult<()>,
     {
         if self.is_emitting_map_key { return Err(EncoderError::BadHashmapKey); }
+        if self.aligns_values() {
+            self.writer.buffers.push(string::String::new());
+            try!(self.paint(COLOR_KEY, |enc| {
+                escape_key(&mut enc.writer, name, enc.json5, enc.escapes)
+            }));
+            let key = self.writer.buffers.pop().unwrap();
+            self.writer.buffers.push(string::String::new());
+            try!(f(self));
+            let value = self.writer.buffers.pop().unwrap();
+            self.entries.last_mut().unwrap().push((name.to_string(), key, value));
+            return Ok(());
+        }
         if idx != 0 {
             try!(write!(self.writer, ","));
         }
+        try!(self.write_comments());
         if let EncodingFormat::Pretty{curr_indent, ..} = self.format {
             try!(write!(self.writer, "\n"));
-            try!(spaces(self.writer, curr_indent));
+            try!(write_indent(&mut self.writer, curr_indent, self.indent_str));
         }
-        try!(escape_str(self.writer, name));
+        try!(self.paint(COLOR_KEY, |enc| escape_key(&mut enc.writer, name, enc.json5, enc.escapes)));
         if let EncodingFormat::Pretty{..} = self.format {
             try!(write!(self.writer, ": "));
         } else {
@@ -851,10 +1888,12 @@ impl<'a> ::Encoder for Encoder<'a> {
                 *curr_indent += indent;
             }
             try!(f(self));
+            try!(self.trailing_comma());
+            try!(self.write_comments());
             if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
                 *curr_indent -= indent;
                 try!(write!(self.writer, "\n"));
-                try!(spaces(self.writer, *curr_indent));
+                try!(write_indent(&mut self.writer, *curr_indent, self.indent_str));
             }
             try!(write!(self.writer, "]"));
         }
@@ -868,9 +1907,10 @@ impl<'a> ::Encoder for Encoder<'a> {
         if idx != 0 {
             try!(write!(self.writer, ","));
         }
+        try!(self.write_comments());
         if let EncodingFormat::Pretty{ref mut curr_indent, ..} = self.format {
             try!(write!(self.writer, "\n"));
-            try!(spaces(self.writer, *curr_indent));
+            try!(write_indent(&mut self.writer, *curr_indent, self.indent_str));
         }
         f(self)
     }
@@ -886,11 +1926,21 @@ impl<'a> ::Encoder for Encoder<'a> {
             if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
                 *curr_indent += indent;
             }
-            try!(f(self));
+            if !self.buffers_maps() {
+                try!(f(self));
+            } else {
+                self.entries.push(Vec::new());
+                try!(f(self));
+                let entries = self.entries.pop().unwrap();
+                let reorder = self.reorders_maps();
+                try!(self.write_ordered_entries(entries, reorder));
+            }
+            try!(self.trailing_comma());
+            try!(self.write_comments());
             if let EncodingFormat::Pretty{ref mut curr_indent, indent} = self.format {
                 *curr_indent -= indent;
                 try!(write!(self.writer, "\n"));
-                try!(spaces(self.writer, *curr_indent));
+                try!(write_indent(&mut self.writer, *curr_indent, self.indent_str));
             }
             try!(write!(self.writer, "}}"));
         }
@@ -901,12 +1951,24 @@ impl<'a> ::Encoder for Encoder<'a> {
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult<()>,
     {
         if self.is_emitting_map_key { return Err(EncoderError::BadHashmapKey); }
+        if self.buffers_maps() {
+            self.writer.buffers.push(string::String::new());
+            self.is_emitting_map_key = true;
+            try!(f(self));
+            self.is_emitting_map_key = false;
+            let encoded = self.writer.buffers.pop().unwrap();
+            let mut key = string::String::new();
+            swap(&mut key, &mut self.map_key);
+            self.entries.last_mut().unwrap().push((key, encoded, string::String::new()));
+            return Ok(());
+        }
         if idx != 0 {
             try!(write!(self.writer, ","));
         }
+        try!(self.write_comments());
         if let EncodingFormat::Pretty{curr_indent, ..} = self.format {
             try!(write!(self.writer, "\n"));
-            try!(spaces(self.writer, curr_indent));
+            try!(write_indent(&mut self.writer, curr_indent, self.indent_str));
         }
         self.is_emitting_map_key = true;
         try!(f(self));
@@ -918,6 +1980,13 @@ impl<'a> ::Encoder for Encoder<'a> {
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult<()>,
     {
         if self.is_emitting_map_key { return Err(EncoderError::BadHashmapKey); }
+        if self.buffers_maps() {
+            self.writer.buffers.push(string::String::new());
+            try!(f(self));
+            let value = self.writer.buffers.pop().unwrap();
+            self.entries.last_mut().unwrap().last_mut().unwrap().2 = value;
+            return Ok(());
+        }
         if let EncodingFormat::Pretty{..} = self.format {
             try!(write!(self.writer, ": "));
         } else {
@@ -925,6 +1994,14 @@ impl<'a> ::Encoder for Encoder<'a> {
         }
         f(self)
     }
+
+    // Maps are sorted as they are written instead, which also covers those
+    // of other types.
+    fn sort_keys(&self) -> bool { false }
+
+    fn error(&mut self, err: &str) -> Option<EncoderError> {
+        Some(EncoderError::ApplicationError(err.to_string()))
+    }
 }
 
 impl Encodable for Json {
@@ -951,12 +2028,79 @@ pub fn as_json<T: Encodable>(t: &T) -> AsJson<T> {
 /// Create an `AsPrettyJson` wrapper which can be used to print a value as JSON
 /// on-the-fly via `write!`
 pub fn as_pretty_json<T: Encodable>(t: &T) -> AsPrettyJson<T> {
-    AsPrettyJson { inner: t, indent: None }
+    AsPrettyJson {
+        inner: t,
+        indent: None,
+        indent_str: None,
+        depth: 0,
+        colored: false,
+        align_values: false,
+    }
+}
+
+// Parses a JSON Pointer reference token as an array index. RFC 6901 allows
+// only decimal digits, without leading zeros.
+fn pointer_index(token: &str) -> Option<usize> {
+    if !token.bytes().all(|b| b'0' <= b && b <= b'9') ||
+       (token.len() > 1 && token.starts_with('0')) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+// Escapes an object key for use as a JSON Pointer reference token.
+fn escape_pointer_token(key: &str) -> string::String {
+    key.replace("~", "~0").replace("/", "~1")
+}
+
+// Turns a JSON Pointer reference token back into the object key it names.
+fn unescape_pointer_token(token: &str) -> string::String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+// The body of `pointer` and `pointer_mut`, which look up the value `$pointer`
+// refers to within `$target`, borrowing each value on the way with `$get`,
+// and mutably if `mut` is given.
+macro_rules! walk_pointer {
+    ($target:expr, $pointer:expr, $get:ident $(, $mut_:tt)*) => ({
+        let pointer = $pointer;
+        if pointer.is_empty() {
+            return Some($target);
+        }
+        if !pointer.starts_with('/') {
+            return None;
+        }
+        let mut target = $target;
+        for token in pointer[1..].split('/') {
+            let token = unescape_pointer_token(token);
+            target = match *target {
+                Json::Object(ref $($mut_)* obj) => match obj.$get(&token) {
+                    Some(t) => t,
+                    None => return None,
+                },
+                Json::Array(ref $($mut_)* array) => {
+                    match pointer_index(&token).and_then(move |i| array.$get(i)) {
+                        Some(t) => t,
+                        None => return None,
+                    }
+                }
+                _ => return None,
+            };
+        }
+        Some(target)
+    })
 }
 
 impl Json {
     /// Decodes a json value from an `&mut io::Read`
     pub fn from_reader(rdr: &mut io::Read) -> Result<Self, BuilderError> {
+        Json::from_reader_with(rdr, &ParserConfig::default())
+    }
+
+    /// Decodes a json value from an `&mut io::Read`, parsing it according to
+    /// the given configuration
+    pub fn from_reader_with(rdr: &mut io::Read,
+                            config: &ParserConfig) -> Result<Self, BuilderError> {
         let contents = {
             let mut c = Vec::new();
             try!(rdr.read_to_end(&mut c));
@@ -966,14 +2110,51 @@ impl Json {
             Some(s) => s,
             _       => return Err(SyntaxError(NotUtf8, 0, 0))
         };
+        let mut builder = Builder::with_config(s.chars(), config.clone());
+        builder.build()
+    }
+
+    /// Decodes a json value from an `&mut io::Read`, replacing any invalid
+    /// UTF-8 sequences with U+FFFD REPLACEMENT CHARACTER rather than failing
+    /// with a `NotUtf8` error.
+    pub fn from_reader_lossy(rdr: &mut io::Read) -> Result<Self, BuilderError> {
+        let mut contents = Vec::new();
+        try!(rdr.read_to_end(&mut contents));
+        Json::from_str(&string::String::from_utf8_lossy(&contents))
+    }
+
+    /// Decodes a json value from a string
+    pub fn from_str(s: &str) -> Result<Self, BuilderError> {
         let mut builder = Builder::new(s.chars());
         builder.build()
     }
 
-    /// Decodes a json value from a string
-    pub fn from_str(s: &str) -> Result<Self, BuilderError> {
-        let mut builder = Builder::new(s.chars());
-        builder.build()
+    /// Decodes a json value from a string, parsing it according to the given
+    /// configuration
+    pub fn from_str_with(s: &str, config: &ParserConfig) -> Result<Self, BuilderError> {
+        let mut builder = Builder::with_config(s.chars(), config.clone());
+        builder.build()
+    }
+
+    /// Decodes a json value from a string like `from_str_with`, also
+    /// returning the uses of the extensions to JSON allowed by `config`
+    /// which were found in it.
+    pub fn from_str_verbose(s: &str, config: &ParserConfig)
+                            -> Result<(Self, Vec<Warning>), BuilderError> {
+        let config = ParserConfig { collect_warnings: true, ..config.clone() };
+        let mut builder = Builder::with_config(s.chars(), config);
+        let json = try!(builder.build());
+        Ok((json, builder.parser.warnings))
+    }
+
+    /// Decodes a json value from a string, accepting a trailing comma at the
+    /// end of arrays and objects
+    pub fn from_str_relaxed(s: &str) -> Result<Self, BuilderError> {
+        let config = ParserConfig {
+            allow_trailing_commas: true,
+            ..ParserConfig::default()
+        };
+        Json::from_str_with(s, &config)
     }
 
     /// Borrow this json object as a pretty object to generate a pretty
@@ -991,6 +2172,29 @@ impl Json {
         }
     }
 
+    /// If the Json value is an Object, inserts `value` under `key`, returning
+    /// the value previously associated with it. A Null value is first
+    /// replaced by an empty Object, so that documents can be built up from
+    /// `Json::Null`. Any other value is left unchanged, and None is returned.
+    pub fn insert(&mut self, key: &str, value: Json) -> Option<Json> {
+        if *self == Json::Null {
+            *self = Json::Object(BTreeMap::new());
+        }
+        match *self {
+            Json::Object(ref mut map) => map.insert(key.to_string(), value),
+            _ => None
+        }
+    }
+
+    /// If the Json value is an Object, removes and returns the value
+    /// associated with the provided key. Otherwise, returns None.
+    pub fn remove(&mut self, key: &str) -> Option<Json> {
+        match *self {
+            Json::Object(ref mut map) => map.remove(key),
+            _ => None
+        }
+    }
+
     /// Attempts to get a nested Json Object for each key in `keys`.
     /// If any key is found not to exist, find_path will return None.
     /// Otherwise, it will return the Json value associated with the final key.
@@ -1005,6 +2209,73 @@ impl Json {
         Some(target)
     }
 
+    /// Looks up a nested value by a path such as `"servers[0].host"`, where
+    /// `.` separates object keys and `[n]` indexes into an array. Keys may
+    /// not contain `.` or `[`; use `find_path` for such keys. An empty path
+    /// refers to this value. Returns None if any step of the path is missing,
+    /// of the wrong type, or malformed.
+    pub fn get_path<'a>(&'a self, path: &str) -> Option<&'a Json> {
+        let mut target = self;
+        let mut rest = path;
+        let mut first = true;
+        while !rest.is_empty() {
+            if rest.starts_with('[') {
+                let end = match rest.find(']') {
+                    Some(end) => end,
+                    None => return None,
+                };
+                let idx = match rest[1..end].parse::<usize>() {
+                    Ok(idx) => idx,
+                    Err(_) => return None,
+                };
+                target = match target.as_array().and_then(|a| a.get(idx)) {
+                    Some(t) => t,
+                    None => return None,
+                };
+                rest = &rest[end + 1..];
+            } else {
+                if !first {
+                    if !rest.starts_with('.') {
+                        return None;
+                    }
+                    rest = &rest[1..];
+                }
+                let end = rest.find(|c| c == '.' || c == '[').unwrap_or(rest.len());
+                target = match target.find(&rest[..end]) {
+                    Some(t) => t,
+                    None => return None,
+                };
+                rest = &rest[end..];
+            }
+            first = false;
+        }
+        Some(target)
+    }
+
+    /// Looks up a nested value by an RFC 6901 JSON Pointer such as
+    /// `"/servers/0/host"`. The empty pointer refers to this value. Returns
+    /// None if the pointer is malformed or does not refer to a value.
+    pub fn pointer<'a>(&'a self, pointer: &str) -> Option<&'a Json> {
+        walk_pointer!(self, pointer, get)
+    }
+
+    /// Like `pointer`, but returns a mutable reference to the value.
+    pub fn pointer_mut<'a>(&'a mut self, pointer: &str) -> Option<&'a mut Json> {
+        walk_pointer!(self, pointer, get_mut, mut)
+    }
+
+    /// Like `pointer`, but returns `default` when the pointer does not refer
+    /// to a value.
+    pub fn pointer_or<'a>(&'a self, pointer: &str, default: &'a Json) -> &'a Json {
+        self.pointer(pointer).unwrap_or(default)
+    }
+
+    /// Like `pointer`, but returns an owned copy of the value so that it can
+    /// outlive `self`.
+    pub fn clone_at(&self, pointer: &str) -> Option<Json> {
+        self.pointer(pointer).cloned()
+    }
+
     /// If the Json value is an Object, performs a depth-first search until
     /// a value associated with the provided key is found. If no value is found
     /// or the Json value is not an Object, returns None.
@@ -1028,6 +2299,556 @@ impl Json {
         }
     }
 
+    /// Returns the RFC 6901 JSON Pointer to each value in this Json value
+    /// which is not an array or an object, in document order. A value which
+    /// is itself a leaf has the single pointer `""`.
+    pub fn leaf_pointers(&self) -> Vec<string::String> {
+        fn collect(json: &Json, path: &mut string::String, out: &mut Vec<string::String>) {
+            let len = path.len();
+            match *json {
+                Json::Array(ref array) => {
+                    for (i, v) in array.iter().enumerate() {
+                        path.push('/');
+                        path.push_str(&i.to_string());
+                        collect(v, path, out);
+                        path.truncate(len);
+                    }
+                }
+                Json::Object(ref obj) => {
+                    for (k, v) in obj.iter() {
+                        path.push('/');
+                        path.push_str(&escape_pointer_token(k));
+                        collect(v, path, out);
+                        path.truncate(len);
+                    }
+                }
+                _ => out.push(path.clone()),
+            }
+        }
+
+        let mut out = Vec::new();
+        collect(self, &mut string::String::new(), &mut out);
+        out
+    }
+
+    /// Returns the length in bytes of the compact encoding of this Json
+    /// value, as written by `to_string` or `encode`, without keeping the
+    /// encoded text in memory.
+    pub fn serialized_len(&self) -> usize {
+        let mut counter = ByteCounter { len: 0 };
+        {
+            let mut encoder = Encoder::new(&mut counter);
+            // Writing to a ByteCounter cannot fail, nor can encoding a Json.
+            let _ = self.encode(&mut encoder);
+        }
+        counter.len
+    }
+
+    /// Renders a compact one line summary of this Json value for log lines
+    /// and error messages, such as `{a: 1, b: [...], c: {}}`. Arrays and
+    /// objects nested inside the value are elided, and the output is cut
+    /// short with `…` so that it is at most `max_len` characters long.
+    pub fn summary(&self, max_len: usize) -> string::String {
+        fn elided(json: &Json) -> string::String {
+            match *json {
+                Json::Array(ref array) if !array.is_empty() => "[...]".to_string(),
+                Json::Object(ref obj) if !obj.is_empty() => "{...}".to_string(),
+                ref other => other.to_string(),
+            }
+        }
+
+        // Stop once the output certainly has more than `max_len` chars.
+        let limit = max_len.saturating_mul(4);
+        let mut out = string::String::new();
+        match *self {
+            Json::Array(ref array) => {
+                out.push('[');
+                for (i, v) in array.iter().enumerate() {
+                    if out.len() > limit { break }
+                    if i != 0 { out.push_str(", "); }
+                    out.push_str(&elided(v));
+                }
+                out.push(']');
+            }
+            Json::Object(ref obj) => {
+                out.push('{');
+                for (i, (k, v)) in obj.iter().enumerate() {
+                    if out.len() > limit { break }
+                    if i != 0 { out.push_str(", "); }
+                    out.push_str(k);
+                    out.push_str(": ");
+                    out.push_str(&elided(v));
+                }
+                out.push('}');
+            }
+            ref other => out = other.to_string(),
+        }
+
+        if out.chars().count() <= max_len {
+            return out;
+        }
+        let mut out: string::String = out.chars().take(max_len.saturating_sub(1)).collect();
+        if max_len > 0 {
+            out.push('\u{2026}');
+        }
+        out
+    }
+
+    /// Flattens this Json value into `(pointer, value)` pairs sorted by their
+    /// RFC 6901 JSON Pointer, one for each value which is not a non-empty
+    /// array or object. This gives a stable list of fields for structured
+    /// logging; `from_sorted_pairs` reverses it.
+    pub fn to_sorted_pairs(&self) -> Vec<(string::String, Json)> {
+        fn collect(json: &Json, path: &mut string::String,
+                   out: &mut Vec<(string::String, Json)>) {
+            let len = path.len();
+            match *json {
+                Json::Array(ref array) if !array.is_empty() => {
+                    for (i, v) in array.iter().enumerate() {
+                        path.push('/');
+                        path.push_str(&i.to_string());
+                        collect(v, path, out);
+                        path.truncate(len);
+                    }
+                }
+                Json::Object(ref obj) if !obj.is_empty() => {
+                    for (k, v) in obj.iter() {
+                        path.push('/');
+                        path.push_str(&escape_pointer_token(k));
+                        collect(v, path, out);
+                        path.truncate(len);
+                    }
+                }
+                _ => out.push((path.clone(), json.clone())),
+            }
+        }
+
+        let mut out = Vec::new();
+        collect(self, &mut string::String::new(), &mut out);
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    /// Rebuilds a Json value from the pairs produced by `to_sorted_pairs`,
+    /// in any order. An object whose keys are exactly `"0"` up to `"n-1"` is
+    /// rebuilt as an array. Returns `None` if a pointer is malformed or if
+    /// two pairs refer to the same or overlapping locations.
+    pub fn from_sorted_pairs(pairs: Vec<(string::String, Json)>) -> Option<Json> {
+        fn into_arrays(json: Json) -> Json {
+            match json {
+                Json::Object(obj) => {
+                    let is_array = !obj.is_empty() && obj.keys().all(|k| {
+                        k.parse::<usize>().ok().map_or(false, |i| {
+                            i < obj.len() && i.to_string() == *k
+                        })
+                    });
+                    if is_array {
+                        let mut array: Vec<(usize, Json)> = obj.into_iter().map(|(k, v)| {
+                            (k.parse().unwrap(), into_arrays(v))
+                        }).collect();
+                        array.sort_by(|a, b| a.0.cmp(&b.0));
+                        Json::Array(array.into_iter().map(|(_, v)| v).collect())
+                    } else {
+                        Json::Object(obj.into_iter().map(|(k, v)| (k, into_arrays(v))).collect())
+                    }
+                }
+                other => other,
+            }
+        }
+
+        let mut root = None;
+        for (pointer, value) in pairs.into_iter() {
+            if pointer.is_empty() {
+                if root.is_some() { return None }
+                root = Some(value);
+                continue
+            }
+            if !pointer.starts_with('/') { return None }
+            let tokens: Vec<string::String> = pointer[1..].split('/')
+                .map(unescape_pointer_token).collect();
+            let mut cur = root.get_or_insert_with(|| Json::Object(BTreeMap::new()));
+            let (last, parents) = tokens.split_last().unwrap();
+            for token in parents {
+                cur = match *cur {
+                    Json::Object(ref mut obj) => {
+                        obj.entry(token.clone()).or_insert_with(|| Json::Object(BTreeMap::new()))
+                    }
+                    _ => return None,
+                };
+            }
+            match *cur {
+                Json::Object(ref mut obj) => {
+                    if obj.insert(last.clone(), value).is_some() { return None }
+                }
+                _ => return None,
+            }
+        }
+        root.map(into_arrays)
+    }
+
+    /// Checks this Json value against `schema`, returning every violation
+    /// found rather than stopping at the first one. Object keys which are not
+    /// mentioned in the schema are accepted.
+    pub fn validate(&self, schema: &JsonSchema) -> Result<(), Vec<SchemaError>> {
+        fn kind(json: &Json) -> &'static str {
+            match *json {
+                Json::I64(_) | Json::U64(_) | Json::F64(_) => "number",
+                Json::String(_) => "string",
+                Json::Boolean(_) => "boolean",
+                Json::Array(_) => "array",
+                Json::Object(_) => "object",
+                Json::Null => "null",
+            }
+        }
+
+        fn check(json: &Json, schema: &JsonSchema, path: &mut string::String,
+                 errors: &mut Vec<SchemaError>) {
+            let len = path.len();
+            let expected = match (schema, json) {
+                (&JsonSchema::Any, _) => return,
+                (&JsonSchema::Null, &Json::Null) |
+                (&JsonSchema::Boolean, &Json::Boolean(_)) |
+                (&JsonSchema::String, &Json::String(_)) => return,
+                (&JsonSchema::Number, _) if json.is_number() => return,
+                (&JsonSchema::Array(ref elem), &Json::Array(ref array)) => {
+                    for (i, v) in array.iter().enumerate() {
+                        path.push('/');
+                        path.push_str(&i.to_string());
+                        check(v, elem, path, errors);
+                        path.truncate(len);
+                    }
+                    return
+                }
+                (&JsonSchema::Object(ref props, ref required), &Json::Object(ref obj)) => {
+                    for key in required.iter() {
+                        if !obj.contains_key(key) {
+                            errors.push(SchemaError::MissingKey(path.clone(), key.clone()));
+                        }
+                    }
+                    for (k, v) in obj.iter() {
+                        if let Some(prop) = props.get(k) {
+                            path.push('/');
+                            path.push_str(&escape_pointer_token(k));
+                            check(v, prop, path, errors);
+                            path.truncate(len);
+                        }
+                    }
+                    return
+                }
+                (&JsonSchema::Null, _) => "null",
+                (&JsonSchema::Boolean, _) => "boolean",
+                (&JsonSchema::Number, _) => "number",
+                (&JsonSchema::String, _) => "string",
+                (&JsonSchema::Array(_), _) => "array",
+                (&JsonSchema::Object(..), _) => "object",
+            };
+            errors.push(SchemaError::TypeMismatch(path.clone(), expected, kind(json)));
+        }
+
+        let mut errors = Vec::new();
+        check(self, schema, &mut string::String::new(), &mut errors);
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Returns true if `subset` is contained in this Json value: every key of
+    /// a `subset` object must be present in the corresponding object with a
+    /// matching value, arrays are matched element-wise up to the length of
+    /// the `subset` array, and any other values must be equal.
+    pub fn contains_subset(&self, subset: &Json) -> bool {
+        match (self, subset) {
+            (&Json::Object(ref map), &Json::Object(ref sub)) => {
+                sub.iter().all(|(k, v)| {
+                    map.get(k).map_or(false, |value| value.contains_subset(v))
+                })
+            }
+            (&Json::Array(ref array), &Json::Array(ref sub)) => {
+                sub.len() <= array.len() &&
+                    array.iter().zip(sub.iter()).all(|(a, b)| a.contains_subset(b))
+            }
+            (value, sub) => value == sub,
+        }
+    }
+
+    /// Compares two Json values for equality, treating an object key which
+    /// maps to `Null` the same as a key which is absent. This applies
+    /// recursively to nested objects, including those inside arrays.
+    pub fn eq_null_as_absent(&self, other: &Json) -> bool {
+        match (self, other) {
+            (&Json::Object(ref a), &Json::Object(ref b)) => {
+                a.iter().all(|(k, v)| match b.get(k) {
+                    Some(other) => v.eq_null_as_absent(other),
+                    None => v.is_null(),
+                }) && b.iter().all(|(k, v)| a.contains_key(k) || v.is_null())
+            }
+            (&Json::Array(ref a), &Json::Array(ref b)) => {
+                a.len() == b.len() &&
+                    a.iter().zip(b.iter()).all(|(a, b)| a.eq_null_as_absent(b))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Compares two Json values for equality, except at the locations named
+    /// by the JSON Pointers in `ignore_ptrs`: the values there may differ,
+    /// or be missing from either side. This is useful for comparing against
+    /// a snapshot which contains timestamps or generated ids.
+    pub fn eq_ignoring(&self, other: &Json, ignore_ptrs: &[&str]) -> bool {
+        fn eq(a: Option<&Json>, b: Option<&Json>, path: &mut string::String,
+              ignore_ptrs: &[&str]) -> bool {
+            if ignore_ptrs.iter().any(|p| *p == &path[..]) {
+                return true;
+            }
+            let len = path.len();
+            match (a, b) {
+                (Some(&Json::Object(ref a)), Some(&Json::Object(ref b))) => {
+                    a.keys().chain(b.keys().filter(|k| !a.contains_key(*k))).all(|k| {
+                        path.push('/');
+                        path.push_str(&escape_pointer_token(k));
+                        let equal = eq(a.get(k), b.get(k), path, ignore_ptrs);
+                        path.truncate(len);
+                        equal
+                    })
+                }
+                (Some(&Json::Array(ref a)), Some(&Json::Array(ref b))) => {
+                    (0..cmp::max(a.len(), b.len())).all(|i| {
+                        path.push('/');
+                        path.push_str(&i.to_string());
+                        let equal = eq(a.get(i), b.get(i), path, ignore_ptrs);
+                        path.truncate(len);
+                        equal
+                    })
+                }
+                (a, b) => a == b,
+            }
+        }
+
+        eq(Some(self), Some(other), &mut string::String::new(), ignore_ptrs)
+    }
+
+    /// Compares two Json values with a total order, so that any values,
+    /// including those holding a NaN, can be sorted.
+    ///
+    /// Unlike `cmp`, values of different variants are ordered by their
+    /// declaration order, so numbers are only compared by value with numbers
+    /// of the same variant. Floats are ordered as by `f64::total_cmp`, so
+    /// `-0.0` sorts before `0.0` and NaNs sort after positive infinity (or
+    /// before negative infinity if their sign bit is set). Arrays and objects
+    /// are compared lexicographically.
+    pub fn total_cmp(&self, other: &Json) -> Ordering {
+        fn rank(json: &Json) -> u8 {
+            match *json {
+                Json::I64(_) => 0,
+                Json::U64(_) => 1,
+                Json::F64(_) => 2,
+                Json::String(_) => 3,
+                Json::Boolean(_) => 4,
+                Json::Array(_) => 5,
+                Json::Object(_) => 6,
+                Json::Null => 7,
+            }
+        }
+
+        fn f64_key(v: f64) -> i64 {
+            // Flip all but the sign bit of negative numbers, so that the
+            // bits compare as signed integers in the same order as the floats
+            let bits = v.to_bits() as i64;
+            bits ^ (((bits >> 63) as u64) >> 1) as i64
+        }
+
+        match (self, other) {
+            (&Json::I64(a), &Json::I64(b)) => a.cmp(&b),
+            (&Json::U64(a), &Json::U64(b)) => a.cmp(&b),
+            (&Json::F64(a), &Json::F64(b)) => f64_key(a).cmp(&f64_key(b)),
+            (&Json::String(ref a), &Json::String(ref b)) => a.cmp(b),
+            (&Json::Boolean(a), &Json::Boolean(b)) => a.cmp(&b),
+            (&Json::Array(ref a), &Json::Array(ref b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    match a.total_cmp(b) {
+                        Ordering::Equal => {}
+                        ord => return ord,
+                    }
+                }
+                a.len().cmp(&b.len())
+            }
+            (&Json::Object(ref a), &Json::Object(ref b)) => {
+                for ((ka, va), (kb, vb)) in a.iter().zip(b.iter()) {
+                    match ka.cmp(kb).then_with(|| va.total_cmp(vb)) {
+                        Ordering::Equal => {}
+                        ord => return ord,
+                    }
+                }
+                a.len().cmp(&b.len())
+            }
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+
+    /// Computes a hash of this Json value which is stable across program runs
+    /// and platforms, for use as a cache key or etag.
+    ///
+    /// Values which compare equal hash identically, and numbers are hashed
+    /// by their numeric value, so `I64(1)`, `U64(1)` and `F64(1.0)` have the
+    /// same hash. All NaNs hash alike, and `-0.0` hashes like `0`. The hash
+    /// is 64-bit FNV-1a, which is not suitable where an attacker controls the
+    /// input.
+    pub fn stable_hash(&self) -> u64 {
+        struct Fnv(u64);
+
+        impl Fnv {
+            fn write(&mut self, bytes: &[u8]) {
+                for &b in bytes {
+                    self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+                }
+            }
+            fn write_u64(&mut self, n: u64) {
+                let mut bytes = [0; 8];
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b = (n >> (i * 8)) as u8;
+                }
+                self.write(&bytes);
+            }
+        }
+
+        fn hash(json: &Json, h: &mut Fnv) {
+            match *json {
+                Json::I64(n) if n >= 0 => hash(&Json::U64(n as u64), h),
+                Json::I64(n) => { h.write(b"i"); h.write_u64(n as u64); }
+                Json::U64(n) => { h.write(b"u"); h.write_u64(n); }
+                Json::F64(f) => {
+                    if f.is_nan() {
+                        h.write(b"n");
+                    } else if f == f.trunc() && f >= 0.0 && f < 18446744073709551616.0 {
+                        hash(&Json::U64(f as u64), h);
+                    } else if f == f.trunc() && f < 0.0 && f >= -9223372036854775808.0 {
+                        hash(&Json::I64(f as i64), h);
+                    } else {
+                        h.write(b"f");
+                        h.write_u64(f.to_bits());
+                    }
+                }
+                Json::String(ref s) => {
+                    h.write(b"s");
+                    h.write_u64(s.len() as u64);
+                    h.write(s.as_bytes());
+                }
+                Json::Boolean(b) => h.write(if b { b"t" } else { b"b" }),
+                Json::Array(ref array) => {
+                    h.write(b"a");
+                    h.write_u64(array.len() as u64);
+                    for v in array { hash(v, h); }
+                }
+                Json::Object(ref obj) => {
+                    h.write(b"o");
+                    h.write_u64(obj.len() as u64);
+                    for (k, v) in obj {
+                        h.write_u64(k.len() as u64);
+                        h.write(k.as_bytes());
+                        hash(v, h);
+                    }
+                }
+                Json::Null => h.write(b"z"),
+            }
+        }
+
+        let mut h = Fnv(0xcbf29ce484222325);
+        hash(self, &mut h);
+        h.0
+    }
+
+    /// Serializes this Json value to bytes in the canonical form defined by
+    /// the JSON Canonicalization Scheme (RFC 8785), suitable for signing or
+    /// hashing: no whitespace, object keys sorted by their UTF-16 code units,
+    /// minimal string escaping and ECMAScript formatting of floats.
+    ///
+    /// Integers are written exactly, even outside the range a double can
+    /// represent, and NaNs and infinities are written as `null` just as the
+    /// `Encoder` writes them.
+    pub fn to_canonical_vec(&self) -> EncodeResult<Vec<u8>> {
+        let mut out = Vec::new();
+        write_canonical(self, &mut out);
+        Ok(out)
+    }
+
+    /// Calls `f` on every string value in this Json value, recursing into
+    /// arrays and objects. Object keys are not visited.
+    pub fn map_strings<F: FnMut(&mut string::String)>(&mut self, mut f: F) {
+        fn map<F: FnMut(&mut string::String)>(json: &mut Json, f: &mut F) {
+            match *json {
+                Json::String(ref mut s) => f(s),
+                Json::Array(ref mut array) => for v in array { map(v, f) },
+                Json::Object(ref mut obj) => for v in obj.values_mut() { map(v, f) },
+                _ => {}
+            }
+        }
+        map(self, &mut f)
+    }
+
+    /// Replaces every `${name}` placeholder in the string values of this
+    /// Json value with `resolve(name)`. Placeholders for which `resolve`
+    /// returns `None` are left as they are.
+    pub fn interpolate<F: Fn(&str) -> Option<string::String>>(&mut self, resolve: F) {
+        self.map_strings(|s| *s = interpolate_str(s, &resolve, &mut None));
+    }
+
+    /// Like `interpolate`, but fails with the name of the first placeholder
+    /// for which `resolve` returns `None`, leaving this value unchanged.
+    pub fn try_interpolate<F>(&mut self, resolve: F) -> Result<(), string::String>
+        where F: Fn(&str) -> Option<string::String>
+    {
+        let mut json = self.clone();
+        let mut missing = None;
+        json.map_strings(|s| *s = interpolate_str(s, &resolve, &mut missing));
+        match missing {
+            Some(name) => Err(name),
+            None => { *self = json; Ok(()) }
+        }
+    }
+
+    /// Calls `f` on every number in this Json value, recursing into arrays
+    /// and objects. The number is passed as the `I64`, `U64` or `F64` Json
+    /// value holding it, which `f` may replace with any other value.
+    pub fn map_numbers<F: FnMut(&mut Json)>(&mut self, mut f: F) {
+        fn map<F: FnMut(&mut Json)>(json: &mut Json, f: &mut F) {
+            match *json {
+                Json::I64(_) | Json::U64(_) | Json::F64(_) => f(json),
+                Json::Array(ref mut array) => for v in array { map(v, f) },
+                Json::Object(ref mut obj) => for v in obj.values_mut() { map(v, f) },
+                _ => {}
+            }
+        }
+        map(self, &mut f)
+    }
+
+    /// Replaces this value with Null, returning the previous value. This moves
+    /// a value out of a document without copying it.
+    pub fn take(&mut self) -> Json {
+        let mut value = Json::Null;
+        swap(self, &mut value);
+        value
+    }
+
+    /// Overlays `other` onto this value. If both are objects, each key of
+    /// `other` is merged into the value of the same key in this object, or
+    /// inserted if it is missing. Otherwise, including when both are arrays,
+    /// this value is replaced by `other`.
+    pub fn merge(&mut self, other: Json) {
+        match other {
+            Json::Object(other) => {
+                if let Some(obj) = self.as_object_mut() {
+                    for (key, value) in other {
+                        match obj.entry(key) {
+                            btree_map::Entry::Occupied(mut e) => e.get_mut().merge(value),
+                            btree_map::Entry::Vacant(e) => { e.insert(value); }
+                        }
+                    }
+                    return;
+                }
+                *self = Json::Object(other);
+            }
+            other => *self = other,
+        }
+    }
+
     /// Returns true if the Json value is an Object. Returns false otherwise.
     pub fn is_object<'a>(&'a self) -> bool {
         self.as_object().is_some()
@@ -1060,6 +2881,12 @@ impl Json {
         }
     }
 
+    /// If the Json value is an Object, returns an iterator over its owned
+    /// key/value pairs. Returns an empty iterator otherwise.
+    pub fn into_object_iter(self) -> btree_map::IntoIter<string::String, Json> {
+        self.into_object().unwrap_or_else(BTreeMap::new).into_iter()
+    }
+
     /// Returns true if the Json value is an Array. Returns false otherwise.
     pub fn is_array<'a>(&'a self) -> bool {
         self.as_array().is_some()
@@ -1092,6 +2919,12 @@ impl Json {
         }
     }
 
+    /// If the Json value is an Array, returns an iterator over its owned
+    /// elements. Returns an empty iterator otherwise.
+    pub fn into_array_iter(self) -> vec::IntoIter<Json> {
+        self.into_array().unwrap_or_else(Vec::new).into_iter()
+    }
+
     /// Returns true if the Json value is a String. Returns false otherwise.
     pub fn is_string<'a>(&'a self) -> bool {
         self.as_string().is_some()
@@ -1106,6 +2939,18 @@ impl Json {
         }
     }
 
+    /// If the Json value is a String, decodes it as base64 and returns the
+    /// result. Returns None otherwise.
+    pub fn as_base64_bytes(&self) -> Option<Result<Vec<u8>, FromBase64Error>> {
+        self.as_string().map(|s| s.from_base64())
+    }
+
+    /// If the Json value is a String, decodes it as hex and returns the
+    /// result. Returns None otherwise.
+    pub fn as_hex_bytes(&self) -> Option<Result<Vec<u8>, FromHexError>> {
+        self.as_string().map(|s| s.from_hex())
+    }
+
     /// Returns true if the Json value is a Number. Returns false otherwise.
     pub fn is_number(&self) -> bool {
         match *self {
@@ -1199,6 +3044,159 @@ impl Json {
     }
 }
 
+/// Values are equal if they are of the same variant and hold equal values.
+/// Unlike for `f64`, all NaNs are equal to each other, so that `==` agrees
+/// with `Ord` and is a true equivalence. As for `f64`, `F64(-0.0)` equals
+/// `F64(0.0)`.
+impl PartialEq for Json {
+    fn eq(&self, other: &Json) -> bool {
+        match (self, other) {
+            (&Json::I64(a), &Json::I64(b)) => a == b,
+            (&Json::U64(a), &Json::U64(b)) => a == b,
+            (&Json::F64(a), &Json::F64(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (&Json::String(ref a), &Json::String(ref b)) => a == b,
+            (&Json::Boolean(a), &Json::Boolean(b)) => a == b,
+            (&Json::Array(ref a), &Json::Array(ref b)) => a == b,
+            (&Json::Object(ref a), &Json::Object(ref b)) => a == b,
+            (&Json::Null, &Json::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Json {}
+
+/// A total order over Json values, so that they can be sorted.
+///
+/// Values are ordered `Null` < `Boolean` < numbers < `String` < `Array` <
+/// `Object`. Numbers of any variant are compared by their exact value, with
+/// NaN greater than every other number, and numbers of equal value by
+/// variant, `I64` < `U64` < `F64`, so that only values which are `==`
+/// compare equal. Floats follow the same rules as `==`: `-0.0` equals `0.0`
+/// and all NaNs are equal. Arrays are compared lexicographically, and
+/// objects lexicographically by their (key, value) pairs in key order. See
+/// also `Json::total_cmp`.
+impl Ord for Json {
+    fn cmp(&self, other: &Json) -> Ordering {
+        fn rank(json: &Json) -> u8 {
+            match *json {
+                Json::Null => 0,
+                Json::Boolean(_) => 1,
+                Json::I64(_) | Json::U64(_) | Json::F64(_) => 2,
+                Json::String(_) => 3,
+                Json::Array(_) => 4,
+                Json::Object(_) => 5,
+            }
+        }
+
+        // The variant order of numbers with equal values
+        fn number_rank(json: &Json) -> u8 {
+            match *json {
+                Json::I64(_) => 0,
+                Json::U64(_) => 1,
+                _ => 2,
+            }
+        }
+
+        match (self, other) {
+            (&Json::Boolean(a), &Json::Boolean(b)) => a.cmp(&b),
+            (&Json::String(ref a), &Json::String(ref b)) => a.cmp(b),
+            (&Json::Array(ref a), &Json::Array(ref b)) => a.cmp(b),
+            (&Json::Object(ref a), &Json::Object(ref b)) => a.cmp(b),
+            (a, b) if rank(a) == 2 && rank(b) == 2 => {
+                cmp_numbers(a, b).then(number_rank(a).cmp(&number_rank(b)))
+            }
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+}
+
+// Compares two numeric Json values by their exact value, with NaN greater
+// than every other number.
+fn cmp_numbers(a: &Json, b: &Json) -> Ordering {
+    fn cmp_i64_f64(a: i64, b: f64) -> Ordering {
+        if b.is_nan() || b >= 9223372036854775808.0 {
+            return Ordering::Less;
+        }
+        if b < -9223372036854775808.0 {
+            return Ordering::Greater;
+        }
+        // b is now in the range of i64, and so is its integer part
+        let int = b.trunc();
+        a.cmp(&(int as i64)).then(int.partial_cmp(&b).unwrap())
+    }
+
+    fn cmp_u64_f64(a: u64, b: f64) -> Ordering {
+        if b.is_nan() || b >= 18446744073709551616.0 {
+            return Ordering::Less;
+        }
+        if b < 0.0 {
+            return Ordering::Greater;
+        }
+        let int = b.trunc();
+        a.cmp(&(int as u64)).then(int.partial_cmp(&b).unwrap())
+    }
+
+    fn cmp_i64_u64(a: i64, b: u64) -> Ordering {
+        if a < 0 { Ordering::Less } else { (a as u64).cmp(&b) }
+    }
+
+    match (a, b) {
+        (&Json::I64(a), &Json::I64(b)) => a.cmp(&b),
+        (&Json::U64(a), &Json::U64(b)) => a.cmp(&b),
+        (&Json::F64(a), &Json::F64(b)) => {
+            match (a.is_nan(), b.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => a.partial_cmp(&b).unwrap(),
+            }
+        }
+        (&Json::I64(a), &Json::U64(b)) => cmp_i64_u64(a, b),
+        (&Json::U64(a), &Json::I64(b)) => cmp_i64_u64(b, a).reverse(),
+        (&Json::I64(a), &Json::F64(b)) => cmp_i64_f64(a, b),
+        (&Json::F64(a), &Json::I64(b)) => cmp_i64_f64(b, a).reverse(),
+        (&Json::U64(a), &Json::F64(b)) => cmp_u64_f64(a, b),
+        (&Json::F64(a), &Json::U64(b)) => cmp_u64_f64(b, a).reverse(),
+        _ => unreachable!(),
+    }
+}
+
+/// Agrees with `Ord`, so unlike for `f64`, a NaN compares greater than any
+/// other float rather than being unordered.
+impl PartialOrd for Json {
+    fn partial_cmp(&self, other: &Json) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Consistent with `==`, so `I64(1)` and `U64(1)` hash differently, and
+/// `F64(0.0)` and `F64(-0.0)` alike. All NaNs hash alike.
+impl Hash for Json {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Json::I64(n) => { state.write_u8(0); n.hash(state) }
+            Json::U64(n) => { state.write_u8(1); n.hash(state) }
+            Json::F64(f) => {
+                state.write_u8(2);
+                let bits = if f == 0.0 {
+                    0
+                } else if f.is_nan() {
+                    f64::NAN.to_bits()
+                } else {
+                    f.to_bits()
+                };
+                bits.hash(state)
+            }
+            Json::String(ref s) => { state.write_u8(3); s.hash(state) }
+            Json::Boolean(b) => { state.write_u8(4); b.hash(state) }
+            Json::Array(ref v) => { state.write_u8(5); v.hash(state) }
+            Json::Object(ref o) => { state.write_u8(6); o.hash(state) }
+            Json::Null => state.write_u8(7),
+        }
+    }
+}
+
 impl<'a> Index<&'a str>  for Json {
     type Output = Json;
 
@@ -1274,7 +3272,7 @@ pub enum StackElement<'l> {
 #[derive(PartialEq, Clone, Debug)]
 enum InternalStackElement {
     InternalIndex(u32),
-    InternalKey(u16, u16), // start, size
+    InternalKey(usize, usize), // start, size
 }
 
 impl Stack {
@@ -1282,6 +3280,12 @@ impl Stack {
         Stack { stack: Vec::new(), str_buffer: Vec::new() }
     }
 
+    // Empties the stack, keeping its allocations for reuse.
+    fn clear(&mut self) {
+        self.stack.clear();
+        self.str_buffer.clear();
+    }
+
     /// Returns The number of elements in the Stack.
     pub fn len(&self) -> usize { self.stack.len() }
 
@@ -1296,7 +3300,7 @@ impl Stack {
             InternalIndex(i) => StackElement::Index(i),
             InternalKey(start, size) => {
                 StackElement::Key(str::from_utf8(
-                    &self.str_buffer[start as usize .. start as usize + size as usize]).unwrap())
+                    &self.str_buffer[start .. start + size]).unwrap())
             }
         }
     }
@@ -1338,7 +3342,7 @@ impl Stack {
             Some(&InternalIndex(i)) => Some(StackElement::Index(i)),
             Some(&InternalKey(start, size)) => {
                 Some(StackElement::Key(str::from_utf8(
-                    &self.str_buffer[start as usize .. (start+size) as usize]
+                    &self.str_buffer[start .. start + size]
                 ).unwrap()))
             }
         }
@@ -1346,10 +3350,8 @@ impl Stack {
 
     // Used by Parser to insert Key elements at the top of the stack.
     fn push_key(&mut self, key: string::String) {
-        self.stack.push(InternalKey(self.str_buffer.len() as u16, key.len() as u16));
-        for c in key.as_bytes().iter() {
-            self.str_buffer.push(*c);
-        }
+        self.stack.push(InternalKey(self.str_buffer.len(), key.len()));
+        self.str_buffer.extend_from_slice(key.as_bytes());
     }
 
     // Used by Parser to insert Index elements at the top of the stack.
@@ -1362,7 +3364,7 @@ impl Stack {
         assert!(!self.is_empty());
         match *self.stack.last().unwrap() {
             InternalKey(_, sz) => {
-                let new_size = self.str_buffer.len() - sz as usize;
+                let new_size = self.str_buffer.len() - sz;
                 self.str_buffer.truncate(new_size);
             }
             InternalIndex(_) => {}
@@ -1390,6 +3392,43 @@ impl Stack {
     }
 }
 
+// Exponents larger than this are clamped while parsing numbers.
+const MAX_EXPONENT: usize = 1000;
+
+/// Options controlling how a `Parser` reads JSON. The default configuration
+/// accepts exactly the JSON grammar of RFC 8259.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParserConfig {
+    /// Report a `DuplicateKey` error when a key appears more than once in
+    /// the same object
+    pub reject_duplicate_keys: bool,
+    /// Skip `//` line comments and `/* */` block comments wherever
+    /// whitespace is allowed
+    pub allow_comments: bool,
+    /// Accept a single trailing comma before the closing `]` or `}` of an
+    /// array or object
+    pub allow_trailing_commas: bool,
+    /// Accept the literals `NaN`, `Infinity` and `-Infinity`, which are
+    /// parsed as `F64` values
+    pub allow_nonfinite: bool,
+    /// Accept object keys which are identifiers, made of ASCII letters,
+    /// digits, `_` and `$` and not starting with a digit, without quotes, as
+    /// written by an `Encoder` set to JSON5
+    pub allow_unquoted_keys: bool,
+    /// The deepest nesting of arrays and objects to accept before reporting
+    /// a `RecursionLimitExceeded` error. There is no limit when this is
+    /// `None`, but input from untrusted sources should always be limited, as
+    /// `Builder` uses a stack frame per level of nesting.
+    pub max_depth: Option<usize>,
+    /// Report syntax errors as `SyntaxErrorAt`, which also carries the byte
+    /// offset of the error in the input
+    pub report_offsets: bool,
+    /// Record each use of the extensions allowed here, returned by
+    /// `Parser::warnings`. The list grows with the input, so this is off by
+    /// default.
+    pub collect_warnings: bool,
+}
+
 /// A streaming JSON parser implemented as an iterator of JsonEvent, consuming
 /// an iterator of char.
 pub struct Parser<T> {
@@ -1397,23 +3436,41 @@ pub struct Parser<T> {
     ch: Option<char>,
     line: usize,
     col: usize,
+    // The byte offset of `ch` in the input.
+    offset: usize,
     // We maintain a stack representing where we are in the logical structure
     // of the JSON stream.
     stack: Stack,
     // A state machine is kept to make it possible to interrupt and resume parsing.
     state: ParserState,
+    // An event parsed ahead of time by `peek`.
+    peeked: Option<JsonEvent>,
+    config: ParserConfig,
+    // The keys seen so far in each enclosing object, when rejecting
+    // duplicate keys.
+    keys: Vec<HashSet<string::String>>,
+    // The position of the last comma between elements, reported if it turns
+    // out to be a trailing one.
+    comma: (usize, usize),
+    warnings: Vec<Warning>,
 }
 
 impl<T: Iterator<Item = char>> Iterator for Parser<T> {
     type Item = JsonEvent;
 
     fn next(&mut self) -> Option<JsonEvent> {
+        if let Some(event) = self.peeked.take() {
+            return Some(event);
+        }
+
         if self.state == ParseFinished {
             return None;
         }
 
         if self.state == ParseBeforeFinish {
-            self.parse_whitespace();
+            if let Err(code) = self.parse_whitespace() {
+                return Some(self.error_event(code));
+            }
             // Make sure there is no trailing characters.
             if self.eof() {
                 self.state = ParseFinished;
@@ -1430,27 +3487,88 @@ impl<T: Iterator<Item = char>> Iterator for Parser<T> {
 impl<T: Iterator<Item = char>> Parser<T> {
     /// Creates the JSON parser.
     pub fn new(rdr: T) -> Parser<T> {
+        Parser::with_config(rdr, ParserConfig::default())
+    }
+
+    /// Creates a JSON parser accepting the extensions enabled in `config`.
+    pub fn with_config(rdr: T, config: ParserConfig) -> Parser<T> {
         let mut p = Parser {
             rdr: rdr,
-            ch: Some('\x00'),
+            ch: None,
             line: 1,
             col: 0,
+            offset: 0,
             stack: Stack::new(),
             state: ParseStart,
+            peeked: None,
+            config: config,
+            keys: Vec::new(),
+            comma: (0, 0),
+            warnings: Vec::new(),
         };
         p.bump();
         return p;
     }
 
+    /// Restarts the parser on a new input, as if it had just been created
+    /// with the same configuration. The buffers of the parser are kept, so a
+    /// parser reused for many small documents avoids reallocating them.
+    pub fn reset(&mut self, rdr: T) {
+        self.rdr = rdr;
+        self.ch = None;
+        self.line = 1;
+        self.col = 0;
+        self.offset = 0;
+        self.stack.clear();
+        self.state = ParseStart;
+        self.peeked = None;
+        self.keys.clear();
+        self.warnings.clear();
+        self.bump();
+    }
+
+    /// Returns the uses of extensions to JSON allowed by the configuration
+    /// of this parser in the input parsed so far, if it has
+    /// `collect_warnings` set.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if self.config.collect_warnings {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Returns the configuration this parser was created with.
+    pub fn config(&self) -> &ParserConfig {
+        &self.config
+    }
+
     /// Provides access to the current position in the logical structure of the
     /// JSON stream.
     pub fn stack<'l>(&'l self) -> &'l Stack {
         return &self.stack;
     }
 
+    /// Returns the next event without consuming it, so that the following
+    /// call to `next` yields the same event.
+    ///
+    /// Note that the event is parsed ahead of time, so `stack` already
+    /// reflects the position after the peeked event.
+    pub fn peek(&mut self) -> Option<&JsonEvent> {
+        if self.peeked.is_none() {
+            self.peeked = self.next();
+        }
+        self.peeked.as_ref()
+    }
+
     fn eof(&self) -> bool { self.ch.is_none() }
     fn ch_or_null(&self) -> char { self.ch.unwrap_or('\x00') }
     fn bump(&mut self) {
+        if let Some(ch) = self.ch {
+            self.offset += ch.len_utf8();
+        }
         self.ch = self.rdr.next();
 
         if self.ch_is('\n') {
@@ -1470,17 +3588,53 @@ impl<T: Iterator<Item = char>> Parser<T> {
     }
 
     fn error<E>(&self, reason: ErrorCode) -> Result<E, ParserError> {
-        Err(SyntaxError(reason, self.line, self.col))
+        Err(self.syntax_error(reason))
+    }
+
+    fn syntax_error(&self, reason: ErrorCode) -> ParserError {
+        if self.config.report_offsets {
+            SyntaxErrorAt(reason, self.line, self.col, self.offset)
+        } else {
+            SyntaxError(reason, self.line, self.col)
+        }
     }
 
-    fn parse_whitespace(&mut self) {
-        while self.ch_is(' ') ||
-              self.ch_is('\n') ||
-              self.ch_is('\t') ||
-              self.ch_is('\r') { self.bump(); }
+    fn parse_whitespace(&mut self) -> Result<(), ErrorCode> {
+        loop {
+            while self.ch_is(' ') ||
+                  self.ch_is('\n') ||
+                  self.ch_is('\t') ||
+                  self.ch_is('\r') { self.bump(); }
+
+            if !(self.config.allow_comments && self.ch_is('/')) {
+                return Ok(());
+            }
+            let (line, col) = (self.line, self.col);
+            self.warn(Warning::Comment(line, col));
+            self.bump();
+            if self.ch_is('/') {
+                while !self.eof() && !self.ch_is('\n') { self.bump(); }
+            } else if self.ch_is('*') {
+                self.bump();
+                loop {
+                    if self.eof() {
+                        return Err(EOFWhileParsingComment);
+                    }
+                    let star = self.ch_is('*');
+                    self.bump();
+                    if star && self.ch_is('/') {
+                        self.bump();
+                        break;
+                    }
+                }
+            } else {
+                return Err(InvalidSyntax);
+            }
+        }
     }
 
     fn parse_number(&mut self) -> JsonEvent {
+        let (line, col) = (self.line, self.col);
         let mut neg = false;
 
         if self.ch_is('-') {
@@ -1488,6 +3642,15 @@ impl<T: Iterator<Item = char>> Parser<T> {
             neg = true;
         }
 
+        if self.config.allow_nonfinite && self.ch_is('I') {
+            let value = if neg { f64::NEG_INFINITY } else { f64::INFINITY };
+            let event = self.parse_ident("nfinity", F64Value(value));
+            if let F64Value(_) = event {
+                self.warn(Warning::NonFiniteNumber(line, col));
+            }
+            return event;
+        }
+
         let res = match self.parse_u64() {
             Ok(res) => res,
             Err(e) => { return Error(e); }
@@ -1519,7 +3682,7 @@ impl<T: Iterator<Item = char>> Parser<T> {
             if neg {
                 // Make sure we don't underflow.
                 if res > (i64::MAX as u64) + 1 {
-                    Error(SyntaxError(InvalidNumber, self.line, self.col))
+                    Error(self.syntax_error(InvalidNumber))
                 } else if res == 0 {
                     I64Value(res as i64)
                 } else {
@@ -1619,8 +3782,13 @@ impl<T: Iterator<Item = char>> Parser<T> {
         while !self.eof() {
             match self.ch_or_null() {
                 c @ '0' ... '9' => {
-                    exp *= 10;
-                    exp += (c as usize) - ('0' as usize);
+                    // Any exponent past this point overflows or underflows an
+                    // f64 whatever the mantissa is, so stop accumulating
+                    // rather than risk overflowing `exp`.
+                    if exp < MAX_EXPONENT {
+                        exp *= 10;
+                        exp += (c as usize) - ('0' as usize);
+                    }
 
                     self.bump();
                 }
@@ -1631,7 +3799,7 @@ impl<T: Iterator<Item = char>> Parser<T> {
         let exp = 10_f64.powi(exp as i32);
         if neg_exp {
             res /= exp;
-        } else {
+        } else if res != 0.0 {
             res *= exp;
         }
 
@@ -1647,6 +3815,7 @@ impl<T: Iterator<Item = char>> Parser<T> {
                 c @ '0' ... '9' => n * 16 + ((c as u16) - ('0' as u16)),
                 c @ 'a' ... 'f' => n * 16 + (10 + (c as u16) - ('a' as u16)),
                 c @ 'A' ... 'F' => n * 16 + (10 + (c as u16) - ('A' as u16)),
+                _ if self.eof() => return self.error(EOFWhileParsingEscape),
                 _ => return self.error(InvalidEscape)
             };
 
@@ -1656,6 +3825,18 @@ impl<T: Iterator<Item = char>> Parser<T> {
         Ok(n)
     }
 
+    fn parse_unquoted_key(&mut self) -> string::String {
+        let mut res = string::String::new();
+        while let Some(c) = self.ch {
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '$') {
+                break;
+            }
+            res.push(c);
+            self.bump();
+        }
+        res
+    }
+
     fn parse_str(&mut self) -> Result<string::String, ParserError> {
         let mut escape = false;
         let mut res = string::String::new();
@@ -1663,6 +3844,9 @@ impl<T: Iterator<Item = char>> Parser<T> {
         loop {
             self.bump();
             if self.eof() {
+                if escape {
+                    return self.error(EOFWhileParsingEscape);
+                }
                 return self.error(EOFWhileParsingString);
             }
 
@@ -1737,7 +3921,9 @@ impl<T: Iterator<Item = char>> Parser<T> {
             // ParseArray(false) and ParseObject(false), which always return,
             // so there is no risk of getting stuck in an infinite loop.
             // All other paths return before the end of the loop's iteration.
-            self.parse_whitespace();
+            if let Err(code) = self.parse_whitespace() {
+                return self.error_event(code);
+            }
 
             match self.state {
                 ParseStart => {
@@ -1758,6 +3944,7 @@ impl<T: Iterator<Item = char>> Parser<T> {
                 ParseObjectComma => {
                     self.stack.pop();
                     if self.ch_is(',') {
+                        self.comma = (self.line, self.col);
                         self.state = ParseObject(false);
                         self.bump();
                     } else {
@@ -1784,9 +3971,14 @@ impl<T: Iterator<Item = char>> Parser<T> {
 
     fn parse_array(&mut self, first: bool) -> JsonEvent {
         if self.ch_is(']') {
-            if !first {
+            if !first && !self.config.allow_trailing_commas {
                 self.error_event(InvalidSyntax)
             } else {
+                if !first {
+                    self.stack.pop();
+                    let (line, col) = self.comma;
+                    self.warn(Warning::TrailingComma(line, col));
+                }
                 self.state = if self.stack.is_empty() {
                     ParseBeforeFinish
                 } else if self.stack.last_is_index() {
@@ -1815,6 +4007,7 @@ impl<T: Iterator<Item = char>> Parser<T> {
     fn parse_array_comma_or_end(&mut self) -> Option<JsonEvent> {
         if self.ch_is(',') {
             self.stack.bump_index();
+            self.comma = (self.line, self.col);
             self.state = ParseArray(false);
             self.bump();
             None
@@ -1838,12 +4031,15 @@ impl<T: Iterator<Item = char>> Parser<T> {
 
     fn parse_object(&mut self, first: bool) -> JsonEvent {
         if self.ch_is('}') {
-            if !first {
+            if !first && !self.config.allow_trailing_commas {
                 if self.stack.is_empty() {
                     return self.error_event(TrailingComma);
                 } else {
                     self.stack.pop();
                 }
+            } else if !first {
+                let (line, col) = self.comma;
+                self.warn(Warning::TrailingComma(line, col));
             }
             self.state = if self.stack.is_empty() {
                 ParseBeforeFinish
@@ -1853,22 +4049,40 @@ impl<T: Iterator<Item = char>> Parser<T> {
                 ParseObjectComma
             };
             self.bump();
+            self.keys.pop();
             return ObjectEnd;
         }
         if self.eof() {
             return self.error_event(EOFWhileParsingObject);
         }
-        if !self.ch_is('"') {
-            return self.error_event(KeyMustBeAString);
-        }
-        let s = match self.parse_str() {
-            Ok(s) => s,
-            Err(e) => {
-                self.state = ParseFinished;
-                return Error(e);
+        let s = match self.ch {
+            Some('"') => match self.parse_str() {
+                Ok(s) => s,
+                Err(e) => {
+                    self.state = ParseFinished;
+                    return Error(e);
+                }
+            },
+            Some(c) if self.config.allow_unquoted_keys &&
+                       (c.is_ascii_alphabetic() || c == '_' || c == '$') => {
+                let (line, col) = (self.line, self.col);
+                self.warn(Warning::UnquotedKey(line, col));
+                self.parse_unquoted_key()
             }
+            _ => return self.error_event(KeyMustBeAString),
         };
-        self.parse_whitespace();
+        if self.config.reject_duplicate_keys {
+            let is_new = match self.keys.last_mut() {
+                Some(keys) => keys.insert(s.clone()),
+                None => true,
+            };
+            if !is_new {
+                return self.error_event(DuplicateKey);
+            }
+        }
+        if let Err(code) = self.parse_whitespace() {
+            return self.error_event(code);
+        }
         if self.eof() {
             return self.error_event(EOFWhileParsingObject);
         } else if self.ch_or_null() != ':' {
@@ -1876,7 +4090,9 @@ impl<T: Iterator<Item = char>> Parser<T> {
         }
         self.stack.push_key(s);
         self.bump();
-        self.parse_whitespace();
+        if let Err(code) = self.parse_whitespace() {
+            return self.error_event(code);
+        }
 
         let val = self.parse_value();
 
@@ -1899,6 +4115,7 @@ impl<T: Iterator<Item = char>> Parser<T> {
                 ParseObjectComma
             };
             self.bump();
+            self.keys.pop();
             ObjectEnd
         } else if self.eof() {
             self.error_event(EOFWhileParsingObject)
@@ -1914,16 +4131,31 @@ impl<T: Iterator<Item = char>> Parser<T> {
             't' => { self.parse_ident("rue", BooleanValue(true)) }
             'f' => { self.parse_ident("alse", BooleanValue(false)) }
             '0' ... '9' | '-' => self.parse_number(),
+            'I' if self.config.allow_nonfinite => self.parse_number(),
+            'N' if self.config.allow_nonfinite => {
+                let (line, col) = (self.line, self.col);
+                let event = self.parse_ident("aN", F64Value(f64::NAN));
+                if let F64Value(_) = event {
+                    self.warn(Warning::NonFiniteNumber(line, col));
+                }
+                event
+            }
             '"' => match self.parse_str() {
                 Ok(s) => StringValue(s),
                 Err(e) => Error(e),
             },
+            '[' | '{' if self.config.max_depth.map_or(false, |max| self.stack.len() >= max) => {
+                self.error_event(RecursionLimitExceeded)
+            }
             '[' => {
                 self.bump();
                 ArrayStart
             }
             '{' => {
                 self.bump();
+                if self.config.reject_duplicate_keys {
+                    self.keys.push(HashSet::new());
+                }
                 ObjectStart
             }
             _ => { self.error_event(InvalidSyntax) }
@@ -1935,13 +4167,193 @@ impl<T: Iterator<Item = char>> Parser<T> {
             self.bump();
             value
         } else {
-            Error(SyntaxError(InvalidSyntax, self.line, self.col))
+            Error(self.syntax_error(InvalidSyntax))
         }
     }
 
     fn error_event(&mut self, reason: ErrorCode) -> JsonEvent {
         self.state = ParseFinished;
-        Error(SyntaxError(reason, self.line, self.col))
+        Error(self.syntax_error(reason))
+    }
+}
+
+// An iterator of the chars of UTF-8 encoded bytes, which ends early if the
+// bytes are not valid UTF-8.
+struct Utf8Chars<I> {
+    bytes: I,
+    invalid: bool,
+}
+
+impl<I: Iterator<Item = u8>> Iterator for Utf8Chars<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.invalid {
+            return None;
+        }
+        let first = match self.bytes.next() {
+            Some(b) if b < 0x80 => return Some(b as char),
+            Some(b) => b,
+            None => return None,
+        };
+        let width = match first {
+            0xC2...0xDF => 2,
+            0xE0...0xEF => 3,
+            0xF0...0xF4 => 4,
+            _ => 0,
+        };
+        let mut buf = [first, 0, 0, 0];
+        for i in 1..width {
+            match self.bytes.next() {
+                Some(b) => buf[i] = b,
+                None => break,
+            }
+        }
+        match str::from_utf8(&buf[..width]) {
+            Ok(s) if width > 0 => s.chars().next(),
+            _ => {
+                self.invalid = true;
+                None
+            }
+        }
+    }
+}
+
+/// A streaming JSON parser like `Parser`, consuming an iterator of UTF-8
+/// encoded bytes rather than of chars. It produces the same events, with the
+/// same positions, as a `Parser` would for the decoded text. Bytes which are
+/// not valid UTF-8 are reported as a `NotUtf8` error at their position.
+///
+/// The bytes are decoded into chars for a `Parser` as they are read, ASCII
+/// bytes being passed through as they are, so this parses at about the same
+/// speed as a `Parser` over the chars of a `str`. What it saves is decoding
+/// the whole input into a `String` first.
+pub struct ByteParser<I> {
+    parser: Parser<Utf8Chars<I>>,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = u8>> ByteParser<I> {
+    /// Creates the JSON parser.
+    pub fn new(bytes: I) -> ByteParser<I> {
+        ByteParser::with_config(bytes, ParserConfig::default())
+    }
+
+    /// Creates a JSON parser accepting the extensions enabled in `config`.
+    pub fn with_config(bytes: I, config: ParserConfig) -> ByteParser<I> {
+        let chars = Utf8Chars { bytes: bytes, invalid: false };
+        ByteParser { parser: Parser::with_config(chars, config), failed: false }
+    }
+
+    /// Provides access to the current position in the logical structure of the
+    /// JSON stream.
+    pub fn stack<'l>(&'l self) -> &'l Stack {
+        self.parser.stack()
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for ByteParser<I> {
+    type Item = JsonEvent;
+
+    fn next(&mut self) -> Option<JsonEvent> {
+        if self.failed {
+            return None;
+        }
+        let event = self.parser.next();
+        if !self.parser.rdr.invalid {
+            return event;
+        }
+        // The events parsed before the invalid bytes were reached stand, but
+        // the end of the input they were taken for is an error.
+        match event {
+            Some(Error(_)) | None => {
+                self.failed = true;
+                Some(self.parser.error_event(NotUtf8))
+            }
+            event => event,
+        }
+    }
+}
+
+// The bytes of a reader, which end early at the first error reading them.
+struct ReadBytes<R> {
+    bytes: io::Bytes<io::BufReader<R>>,
+    error: Option<io::Error>,
+}
+
+impl<R: io::Read> Iterator for ReadBytes<R> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.bytes.next() {
+            Some(Ok(b)) => Some(b),
+            Some(Err(e)) => {
+                self.error = Some(e);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+/// A streaming JSON parser like `ByteParser`, pulling UTF-8 encoded bytes
+/// from a reader as events are requested, so that a large document can be
+/// walked without holding all of it in memory. The reader is buffered
+/// internally. An error reading from it is reported as an `IoError` event,
+/// which ends the stream.
+pub struct ReaderParser<R> {
+    parser: ByteParser<ReadBytes<R>>,
+    failed: bool,
+}
+
+impl<R: io::Read> ReaderParser<R> {
+    /// Creates the JSON parser.
+    pub fn new(reader: R) -> ReaderParser<R> {
+        ReaderParser::with_config(reader, ParserConfig::default())
+    }
+
+    /// Creates a JSON parser accepting the extensions enabled in `config`.
+    pub fn with_config(reader: R, config: ParserConfig) -> ReaderParser<R> {
+        let bytes = ReadBytes { bytes: io::BufReader::new(reader).bytes(), error: None };
+        ReaderParser { parser: ByteParser::with_config(bytes, config), failed: false }
+    }
+
+    /// Provides access to the current position in the logical structure of the
+    /// JSON stream.
+    pub fn stack<'l>(&'l self) -> &'l Stack {
+        self.parser.stack()
+    }
+}
+
+impl<R: io::Read> Iterator for ReaderParser<R> {
+    type Item = JsonEvent;
+
+    fn next(&mut self) -> Option<JsonEvent> {
+        if self.failed {
+            return None;
+        }
+        let event = self.parser.next();
+        let error = match self.parser.parser.rdr.bytes.error.take() {
+            Some(error) => error,
+            None => return event,
+        };
+        // The end of the input taken for the end of the document is an error,
+        // as is a number which the unread input might have continued.
+        match event {
+            Some(Error(_)) | None |
+            Some(I64Value(_)) | Some(U64Value(_)) | Some(F64Value(_)) => {
+                self.failed = true;
+                self.parser.failed = true;
+                Some(Error(IoError(error)))
+            }
+            event => {
+                self.parser.parser.rdr.bytes.error = Some(error);
+                event
+            }
+        }
     }
 }
 
@@ -1957,6 +4369,12 @@ impl<T: Iterator<Item = char>> Builder<T> {
         Builder { parser: Parser::new(src), token: None, }
     }
 
+    /// Create a JSON Builder whose parser accepts the extensions enabled in
+    /// `config`.
+    pub fn with_config(src: T, config: ParserConfig) -> Builder<T> {
+        Builder { parser: Parser::with_config(src, config), token: None, }
+    }
+
     // Decode a Json value from a Parser.
     pub fn build(&mut self) -> Result<Json, BuilderError> {
         self.bump();
@@ -1965,11 +4383,28 @@ impl<T: Iterator<Item = char>> Builder<T> {
         match self.token.take() {
             None => {}
             Some(Error(e)) => { return Err(e); }
-            _ => { return Err(SyntaxError(InvalidSyntax, self.parser.line, self.parser.col)); }
+            _ => { return self.parser.error(InvalidSyntax); }
         }
         result
     }
 
+    /// Decode the next of a series of whitespace separated Json values,
+    /// leaving the builder ready to decode the one after it. Returns
+    /// `Ok(None)` once the end of the input is reached.
+    pub fn build_one(&mut self) -> Result<Option<Json>, BuilderError> {
+        if let Err(code) = self.parser.parse_whitespace() {
+            return self.parser.error(code);
+        }
+        if self.parser.eof() {
+            return Ok(None);
+        }
+        if self.parser.state == ParseBeforeFinish {
+            self.parser.state = ParseStart;
+        }
+        self.bump();
+        self.build_value().map(Some)
+    }
+
     fn bump(&mut self) {
         self.token = self.parser.next();
     }
@@ -2040,12 +4475,93 @@ impl<T: Iterator<Item = char>> Builder<T> {
 /// A structure to decode JSON to values in rust.
 pub struct Decoder {
     stack: Vec<Json>,
+    base64_bytes: bool,
+    reject_duplicate_keys: bool,
+    // The keys left over in decoded structs, when recording them.
+    ignored: Option<Vec<string::String>>,
+    // Set while decoding the Null standing in for an absent struct field.
+    absent_field: bool,
 }
 
 impl Decoder {
     /// Creates a new decoder instance for decoding the specified JSON value.
     pub fn new(json: Json) -> Decoder {
-        Decoder { stack: vec![json] }
+        Decoder {
+            stack: vec![json],
+            base64_bytes: false,
+            reject_duplicate_keys: false,
+            ignored: None,
+            absent_field: false,
+        }
+    }
+
+    /// Also accept a base64 encoded string where a sequence of bytes is
+    /// expected. This allows a `Vec<u8>` to be decoded from either a JSON
+    /// array of numbers or a base64 string.
+    pub fn set_base64_bytes(&mut self, enabled: bool) {
+        self.base64_bytes = enabled;
+    }
+
+    /// Fail with `DuplicateKeyError` when a map given in the array of
+    /// `[key, value]` pairs form contains the same key more than once,
+    /// rather than keeping the last value for it.
+    pub fn set_reject_duplicate_keys(&mut self, enabled: bool) {
+        self.reject_duplicate_keys = enabled;
+    }
+
+    /// Record the keys of objects decoded as structs which do not match any
+    /// of the struct's fields, which are otherwise silently ignored. They are
+    /// available from `ignored_fields` once decoding is done.
+    pub fn set_record_ignored_fields(&mut self, enabled: bool) {
+        self.ignored = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the ignored keys recorded so far, in the order they were
+    /// found, if `set_record_ignored_fields` is enabled.
+    pub fn ignored_fields(&self) -> &[string::String] {
+        match self.ignored {
+            Some(ref ignored) => ignored,
+            None => &[],
+        }
+    }
+
+    /// Reads an enum encoded as its integer discriminant rather than its
+    /// variant name. `table` maps each discriminant value to the index of the
+    /// variant it represents, which is passed on to `f` just like
+    /// `read_enum_variant` does.
+    pub fn read_enum_from_u64<T, F>(&mut self, table: &[(u64, usize)],
+                                    f: F) -> DecodeResult<T>
+        where F: FnOnce(&mut Decoder, usize) -> DecodeResult<T>,
+    {
+        let value = try!(::Decoder::read_u64(self));
+        let idx = match table.iter().find(|&&(v, _)| v == value) {
+            Some(&(_, idx)) => idx,
+            None => {
+                let expected = table.iter().map(|&(v, _)| v.to_string()).collect();
+                return Err(UnexpectedVariantError(value.to_string(), expected))
+            }
+        };
+        f(self, idx)
+    }
+
+    /// Reads a struct field which may be present under any of the given
+    /// names, such as a field which has been renamed. The first name present
+    /// in the object is decoded; if none is, this behaves like
+    /// `read_struct_field` with the first name. Fails with
+    /// `ApplicationError` if `names` is empty.
+    pub fn read_struct_field_aliased<T, F>(&mut self, names: &[&str], idx: usize,
+                                           f: F) -> DecodeResult<T>
+        where F: FnOnce(&mut Decoder) -> DecodeResult<T>,
+    {
+        let name = match self.stack.last() {
+            Some(&Json::Object(ref obj)) => names.iter().find(|n| obj.contains_key(**n)),
+            _ => None,
+        };
+        let name = match name.or(names.first()) {
+            Some(name) => *name,
+            None => return Err(ApplicationError("no names given for a struct field".to_string())),
+        };
+        ::Decoder::read_struct_field(self, name, idx, f)
     }
 }
 
@@ -2127,11 +4643,13 @@ impl ::Decoder for Decoder {
     read_primitive! { read_u16, u16 }
     read_primitive! { read_u32, u32 }
     read_primitive! { read_u64, u64 }
+    read_primitive! { read_u128, u128 }
     read_primitive! { read_isize, isize }
     read_primitive! { read_i8, i8 }
     read_primitive! { read_i16, i16 }
     read_primitive! { read_i32, i32 }
     read_primitive! { read_i64, i64 }
+    read_primitive! { read_i128, i128 }
 
     fn read_f32(&mut self) -> DecodeResult<f32> {
         self.read_f64().map(|x| x as f32)
@@ -2176,6 +4694,13 @@ impl ::Decoder for Decoder {
         expect!(self.pop(), String)
     }
 
+    fn read_str_or_seq<T: ::Decodable>(&mut self) -> DecodeResult<::StrOrSeq<T>> {
+        match self.stack.last() {
+            Some(&Json::Array(_)) => ::Decodable::decode(self).map(::StrOrSeq::Seq),
+            _ => ::Decoder::read_str(self).map(::StrOrSeq::Str),
+        }
+    }
+
     fn read_enum<T, F>(&mut self, _name: &str, f: F) -> DecodeResult<T> where
         F: FnOnce(&mut Decoder) -> DecodeResult<T>,
     {
@@ -2219,7 +4744,10 @@ impl ::Decoder for Decoder {
         };
         let idx = match names.iter().position(|n| *n == name) {
             Some(idx) => idx,
-            None => return Err(UnknownVariantError(name))
+            None => {
+                let expected = names.iter().map(|n| n.to_string()).collect();
+                return Err(UnexpectedVariantError(name, expected))
+            }
         };
         f(self, idx)
     }
@@ -2251,7 +4779,10 @@ impl ::Decoder for Decoder {
         F: FnOnce(&mut Decoder) -> DecodeResult<T>,
     {
         let value = try!(f(self));
-        try!(self.pop());
+        let rest = try!(self.pop());
+        if let (Some(ref mut ignored), Json::Object(rest)) = (self.ignored.as_mut(), rest) {
+            ignored.extend(rest.into_iter().map(|(k, _)| k));
+        }
         Ok(value)
     }
 
@@ -2269,7 +4800,10 @@ impl ::Decoder for Decoder {
                 // Add a Null and try to parse it as an Option<_>
                 // to get None as a default value.
                 self.stack.push(Json::Null);
-                match f(self) {
+                self.absent_field = true;
+                let result = f(self);
+                self.absent_field = false;
+                match result {
                     Ok(x) => x,
                     Err(_) => return Err(MissingFieldError(name.to_string())),
                 }
@@ -2324,7 +4858,18 @@ impl ::Decoder for Decoder {
         F: FnMut(&mut Decoder, bool) -> DecodeResult<T>,
     {
         match try!(self.pop()) {
-            Json::Null => f(self, false),
+            Json::Null => { self.absent_field = false; f(self, false) }
+            value => { self.stack.push(value); f(self, true) }
+        }
+    }
+
+    fn read_nested_option<T, F>(&mut self, mut f: F) -> DecodeResult<T> where
+        F: FnMut(&mut Decoder, bool) -> DecodeResult<T>,
+    {
+        match try!(self.pop()) {
+            Json::Null if self.absent_field => { self.absent_field = false; f(self, false) }
+            // A null which is present is `Some` of the inner option, which
+            // reads it as `None`.
             value => { self.stack.push(value); f(self, true) }
         }
     }
@@ -2332,7 +4877,10 @@ impl ::Decoder for Decoder {
     fn read_seq<T, F>(&mut self, f: F) -> DecodeResult<T> where
         F: FnOnce(&mut Decoder, usize) -> DecodeResult<T>,
     {
-        let array = try!(expect!(self.pop(), Array));
+        let array = match try!(self.pop()) {
+            Json::Array(array) => array,
+            other => return Err(ExpectedError("Array".to_string(), format!("{}", other))),
+        };
         let len = array.len();
         for v in array.into_iter().rev() {
             self.stack.push(v);
@@ -2340,6 +4888,27 @@ impl ::Decoder for Decoder {
         f(self, len)
     }
 
+    fn read_bytes<T, F>(&mut self, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder, usize) -> DecodeResult<T>,
+    {
+        let bytes = match self.stack.pop() {
+            Some(Json::String(s)) if self.base64_bytes => match s.from_base64() {
+                Ok(bytes) => bytes,
+                Err(_) => return Err(ExpectedError("Base64".to_string(), s)),
+            },
+            Some(value) => {
+                self.stack.push(value);
+                return ::Decoder::read_seq(self, f);
+            }
+            None => return Err(EOF),
+        };
+        let len = bytes.len();
+        for b in bytes.into_iter().rev() {
+            self.stack.push(Json::U64(b as u64));
+        }
+        f(self, len)
+    }
+
     fn read_seq_elt<T, F>(&mut self, _idx: usize, f: F) -> DecodeResult<T> where
         F: FnOnce(&mut Decoder) -> DecodeResult<T>,
     {
@@ -2349,12 +4918,44 @@ impl ::Decoder for Decoder {
     fn read_map<T, F>(&mut self, f: F) -> DecodeResult<T> where
         F: FnOnce(&mut Decoder, usize) -> DecodeResult<T>,
     {
-        let obj = try!(expect!(self.pop(), Object));
-        let len = obj.len();
-        for (key, value) in obj.into_iter() {
-            self.stack.push(value);
-            self.stack.push(Json::String(key));
-        }
+        let len = match try!(self.pop()) {
+            Json::Object(obj) => {
+                let len = obj.len();
+                for (key, value) in obj.into_iter() {
+                    self.stack.push(value);
+                    self.stack.push(Json::String(key));
+                }
+                len
+            }
+            // maps whose keys cannot be object keys, as `[[key, value], ...]`
+            Json::Array(pairs) => {
+                let len = pairs.len();
+                let mut seen = HashSet::new();
+                // Pushed in reverse so that the pairs are read in order and
+                // the last of any duplicate keys wins.
+                for pair in pairs.into_iter().rev() {
+                    match pair {
+                        Json::Array(mut kv) if kv.len() == 2 => {
+                            let value = kv.pop().unwrap();
+                            let key = kv.pop().unwrap();
+                            if self.reject_duplicate_keys && !seen.insert(key.to_string()) {
+                                return Err(DuplicateKeyError(key.to_string()));
+                            }
+                            self.stack.push(value);
+                            self.stack.push(key);
+                        }
+                        other => {
+                            return Err(ExpectedError("Array of [key, value] pairs".to_string(),
+                                                     format!("{}", other)))
+                        }
+                    }
+                }
+                len
+            }
+            other => {
+                return Err(ExpectedError("Object".to_string(), format!("{}", other)))
+            }
+        };
         f(self, len)
     }
 
@@ -2428,6 +5029,10 @@ impl ToJson for bool {
     fn to_json(&self) -> Json { Json::Boolean(*self) }
 }
 
+impl ToJson for char {
+    fn to_json(&self) -> Json { Json::String(self.to_string()) }
+}
+
 impl ToJson for str {
     fn to_json(&self) -> Json { Json::String(self.to_string()) }
 }
@@ -2496,6 +5101,15 @@ impl<A: ToJson> ToJson for HashMap<string::String, A> {
     }
 }
 
+impl<A: ToJson> ToJson for BTreeSet<A> {
+    fn to_json(&self) -> Json { Json::Array(self.iter().map(|elt| elt.to_json()).collect()) }
+}
+
+/// The elements are in the set's iteration order, which is unspecified.
+impl<A: ToJson + Eq + Hash> ToJson for HashSet<A> {
+    fn to_json(&self) -> Json { Json::Array(self.iter().map(|elt| elt.to_json()).collect()) }
+}
+
 impl<A:ToJson> ToJson for Option<A> {
     fn to_json(&self) -> Json {
         match *self {
@@ -2505,6 +5119,41 @@ impl<A:ToJson> ToJson for Option<A> {
     }
 }
 
+/// Converted as by `Encodable`, to `{"variant": "Ok", "fields": [value]}` or
+/// `{"variant": "Err", "fields": [error]}`, so that the result can be decoded.
+impl<T: ToJson, E: ToJson> ToJson for Result<T, E> {
+    fn to_json(&self) -> Json {
+        let (variant, value) = match *self {
+            Ok(ref value) => ("Ok", value.to_json()),
+            Err(ref error) => ("Err", error.to_json()),
+        };
+        let mut d = BTreeMap::new();
+        d.insert("variant".to_string(), Json::String(variant.to_string()));
+        d.insert("fields".to_string(), Json::Array(vec![value]));
+        Json::Object(d)
+    }
+}
+
+impl<'a, T: ToJson + ?Sized> ToJson for &'a T {
+    fn to_json(&self) -> Json { (**self).to_json() }
+}
+
+impl<'a, T: ToJson + ?Sized> ToJson for &'a mut T {
+    fn to_json(&self) -> Json { (**self).to_json() }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Box<T> {
+    fn to_json(&self) -> Json { (**self).to_json() }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Rc<T> {
+    fn to_json(&self) -> Json { (**self).to_json() }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Arc<T> {
+    fn to_json(&self) -> Json { (**self).to_json() }
+}
+
 struct FormatShim<'a, 'b: 'a> {
     inner: &'a mut fmt::Formatter<'b>,
 }
@@ -2518,6 +5167,18 @@ impl<'a, 'b> fmt::Write for FormatShim<'a, 'b> {
     }
 }
 
+// Counts the bytes written to it, discarding them.
+struct ByteCounter {
+    len: usize,
+}
+
+impl fmt::Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.len += s.len();
+        Ok(())
+    }
+}
+
 impl fmt::Display for Json {
     /// Encodes a json value into a string
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -2558,6 +5219,38 @@ impl<'a, T> AsPrettyJson<'a, T> {
     /// Set the indentation level for the emitted JSON
     pub fn indent(mut self, indent: u32) -> AsPrettyJson<'a, T> {
         self.indent = Some(indent);
+        self.indent_str = None;
+        self
+    }
+
+    /// Indent each level of the emitted JSON with a copy of `indent_str`,
+    /// such as `"\t"`
+    pub fn indent_str(mut self, indent_str: &'a str) -> AsPrettyJson<'a, T> {
+        self.indent = None;
+        self.indent_str = Some(indent_str);
+        self
+    }
+
+    /// Start the emitted JSON at the given nesting depth, so that a value
+    /// found at that depth of a `Stack` is indented as it was in its parent
+    /// document. The first line is never indented.
+    pub fn at_depth(mut self, stack_len: u32) -> AsPrettyJson<'a, T> {
+        self.depth = stack_len;
+        self
+    }
+
+    /// Highlight keys, strings, numbers and literals with ANSI color escapes,
+    /// for printing to a terminal. This has no effect unless the `color`
+    /// feature is enabled.
+    pub fn colored(mut self) -> AsPrettyJson<'a, T> {
+        self.colored = true;
+        self
+    }
+
+    /// Pad the keys of each object to the width of its longest key, so that
+    /// all of the object's values start in the same column
+    pub fn align_values(mut self, align: bool) -> AsPrettyJson<'a, T> {
+        self.align_values = align;
         self
     }
 }
@@ -2571,6 +5264,14 @@ impl<'a, T: Encodable> fmt::Display for AsPrettyJson<'a, T> {
             // unwrap cannot panic for pretty encoders
             let _ = encoder.set_indent(n);
         }
+        if let Some(s) = self.indent_str {
+            let _ = encoder.set_indent_str(s);
+        }
+        if let EncodingFormat::Pretty{ref mut curr_indent, indent} = encoder.format {
+            *curr_indent = self.depth * indent;
+        }
+        encoder.colored = self.colored;
+        encoder.set_align_values(self.align_values);
         match self.inner.encode(&mut encoder) {
             Ok(_) => Ok(()),
             Err(_) => Err(fmt::Error)
@@ -2628,6 +5329,35 @@ mod tests {
                                 ExpectedError("Number".to_string(), "false".to_string()));
     }
 
+    #[derive(RustcDecodable, Eq, PartialEq, Debug)]
+    struct NestedOptionData {
+        opt: Option<Option<usize>>,
+    }
+
+    #[test]
+    fn test_decode_nested_option() {
+        let obj: NestedOptionData = super::decode("{}").unwrap();
+        assert_eq!(obj, NestedOptionData { opt: None });
+        let obj: NestedOptionData = super::decode("{ \"opt\": null }").unwrap();
+        assert_eq!(obj, NestedOptionData { opt: Some(None) });
+        let obj: NestedOptionData = super::decode("{ \"opt\": 5 }").unwrap();
+        assert_eq!(obj, NestedOptionData { opt: Some(Some(5)) });
+
+        // Plain options still decode null as `None`
+        let obj: OptionData = super::decode("{ \"opt\": null }").unwrap();
+        assert_eq!(obj, OptionData { opt: None });
+        let value: Vec<Option<Option<f64>>> = super::decode("[null, 1]").unwrap();
+        assert_eq!(value, vec![Some(None), Some(Some(1.0))]);
+        let value: Option<f64> = super::decode("null").unwrap();
+        assert_eq!(value, None);
+        let value: Option<()> = super::decode("null").unwrap();
+        assert_eq!(value, None);
+        let value: Option<Option<Option<u8>>> = super::decode("null").unwrap();
+        assert_eq!(value, Some(Some(None)));
+        let value: Option<Option<()>> = super::decode("null").unwrap();
+        assert_eq!(value, Some(None));
+    }
+
     #[derive(PartialEq, RustcEncodable, RustcDecodable, Debug)]
     enum Animal {
         Dog,
@@ -2675,14 +5405,128 @@ mod tests {
         assert_eq!(U64(0).to_string(), "0");
         assert_eq!(U64(0).pretty().to_string(), "0");
 
-        assert_eq!(U64(1234).to_string(), "1234");
-        assert_eq!(U64(1234).pretty().to_string(), "1234");
+        assert_eq!(U64(1234).to_string(), "1234");
+        assert_eq!(U64(1234).pretty().to_string(), "1234");
+
+        assert_eq!(I64(-5678).to_string(), "-5678");
+        assert_eq!(I64(-5678).pretty().to_string(), "-5678");
+
+        assert_eq!(U64(7650007200025252000).to_string(), "7650007200025252000");
+        assert_eq!(U64(7650007200025252000).pretty().to_string(), "7650007200025252000");
+    }
+
+    #[test]
+    fn test_write_i128() {
+        use std::{i128, u128};
+
+        assert_eq!(super::encode(&u128::MAX).unwrap(),
+                   "\"340282366920938463463374607431768211455\"");
+        assert_eq!(super::encode(&i128::MIN).unwrap(),
+                   "\"-170141183460469231731687303715884105728\"");
+        assert_eq!(super::encode(&vec![1u128, 2]).unwrap(), "[1,2]");
+        assert_eq!(super::encode(&vec![-1i128, 18446744073709551615]).unwrap(),
+                   "[-1,18446744073709551615]");
+        assert_eq!(super::encode(&18446744073709551616u128).unwrap(),
+                   "\"18446744073709551616\"");
+
+        for &v in &[0, u64::MAX as u128, u64::MAX as u128 + 1, u128::MAX] {
+            assert_eq!(super::decode::<u128>(&super::encode(&v).unwrap()).unwrap(), v);
+        }
+        for &v in &[i128::MIN, i64::MIN as i128 - 1, i64::MIN as i128, -1, u64::MAX as i128,
+                    u64::MAX as i128 + 1, i128::MAX] {
+            assert_eq!(super::decode::<i128>(&super::encode(&v).unwrap()).unwrap(), v);
+        }
+
+        let mut map = BTreeMap::new();
+        map.insert(i128::MAX, true);
+        assert_eq!(super::encode(&map).unwrap(),
+                   r#"{"170141183460469231731687303715884105727":true}"#);
+        let decoded: BTreeMap<i128, bool> = super::decode(&super::encode(&map).unwrap()).unwrap();
+        assert_eq!(decoded, map);
+
+        let decoded: Vec<i128> = super::decode("[-3, 18446744073709551615]").unwrap();
+        assert_eq!(decoded, vec![-3, 18446744073709551615]);
+        let decoded: u128 = super::decode("\"340282366920938463463374607431768211455\"").unwrap();
+        assert_eq!(decoded, u128::MAX);
+        assert!(super::decode::<u128>("-1").is_err());
+    }
+
+    #[test]
+    fn test_float_format() {
+        use std::f64;
+        use super::FloatFormat::{self, Shortest, Fixed, Significant};
+
+        fn encode(v: f64, format: FloatFormat) -> string::String {
+            let mut s = string::String::new();
+            {
+                let mut encoder = Encoder::new(&mut s);
+                encoder.set_float_format(format);
+                v.encode(&mut encoder).unwrap();
+            }
+            s
+        }
+
+        assert_eq!(encode(0.1, Shortest), "0.1");
+        assert_eq!(encode(1e20, Shortest), super::encode(&1e20).unwrap());
+        assert_eq!(encode(0.1, Fixed(3)), "0.100");
+        assert_eq!(encode(-2.0 / 3.0, Fixed(2)), "-0.67");
+        assert_eq!(encode(2.5, Fixed(0)), "2");
+        assert_eq!(encode(0.1, Significant(6)), "0.100000");
+        assert_eq!(encode(123456.7, Significant(6)), "123457");
+        assert_eq!(encode(999999.7, Significant(6)), "1.00000e6");
+        assert_eq!(encode(1e20, Significant(3)), "1.00e20");
+        assert_eq!(encode(-0.000012345, Significant(2)), "-1.2e-5");
+        assert_eq!(encode(0.00012345, Significant(2)), "0.00012");
+        assert_eq!(encode(7.0, Significant(0)), "7");
+        for &format in &[Shortest, Fixed(2), Significant(4)] {
+            assert_eq!(encode(f64::NAN, format), "null");
+            assert_eq!(encode(f64::NEG_INFINITY, format), "null");
+            let s = encode(-1234.5678e-3, format);
+            assert!(Json::from_str(&s).unwrap().is_number(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_float_formatter() {
+        use std::{f64, fmt};
+        use super::EncodeResult;
+
+        fn encode<T: Encodable>(t: &T, formatter: &Fn(f64, &mut fmt::Write) -> fmt::Result)
+                                -> EncodeResult<string::String> {
+            let mut s = string::String::new();
+            {
+                let mut encoder = Encoder::new(&mut s);
+                encoder.set_float_formatter(formatter);
+                try!(t.encode(&mut encoder));
+            }
+            Ok(s)
+        }
+
+        let fixed = |v: f64, wr: &mut fmt::Write| write!(wr, "{:.3}", v);
+        let values = vec![1.0, -0.0005, 2.0f64 / 3.0, 1e10, f64::NAN];
+        assert_eq!(encode(&values, &fixed).unwrap(),
+                   "[1.000,-0.001,0.667,10000000000.000,null]");
+        assert_eq!(encode(&vec![0.25f32], &fixed).unwrap(), "[0.250]");
+        assert_eq!(encode(&vec![5u8], &fixed).unwrap(), "[5]");
+
+        let mut map = BTreeMap::new();
+        map.insert(F64(0.5).to_string(), 1.5);
+        assert_eq!(encode(&map, &fixed).unwrap(), r#"{"0.5":1.500}"#);
 
-        assert_eq!(I64(-5678).to_string(), "-5678");
-        assert_eq!(I64(-5678).pretty().to_string(), "-5678");
+        let separated = |v: f64, wr: &mut fmt::Write| write!(wr, "{},000", v);
+        assert_eq!(encode(&1.0, &separated), Err(EncoderError::BadNumber("1,000".to_string())));
 
-        assert_eq!(U64(7650007200025252000).to_string(), "7650007200025252000");
-        assert_eq!(U64(7650007200025252000).pretty().to_string(), "7650007200025252000");
+        // Whichever of the format and the formatter is set last is used.
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_float_format(super::FloatFormat::Fixed(1));
+            encoder.set_float_formatter(&fixed);
+            0.5.encode(&mut encoder).unwrap();
+            encoder.set_float_format(super::FloatFormat::Fixed(1));
+            0.5.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, "0.5000.5");
     }
 
     #[test]
@@ -2826,6 +5670,194 @@ mod tests {
         assert_eq!(a.clone(), a.pretty().to_string().parse().unwrap());
     }
 
+    #[test]
+    fn test_write_object_key_order() {
+        let obj = mk_object(&[
+            ("z".to_string(), U64(1)),
+            ("type".to_string(), String("t".to_string())),
+            ("a".to_string(), mk_object(&[
+                ("b".to_string(), Null),
+                ("id".to_string(), U64(3)),
+            ])),
+            ("id".to_string(), U64(2)),
+        ]);
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_key_order(&["id", "type"]);
+            obj.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, r#"{"id":2,"type":"t","a":{"id":3,"b":null},"z":1}"#);
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new_pretty(&mut s);
+            encoder.set_key_order(&["id", "type"]);
+            obj.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, "\
+            {\n  \
+                \"id\": 2,\n  \
+                \"type\": \"t\",\n  \
+                \"a\": {\n    \
+                    \"id\": 3,\n    \
+                    \"b\": null\n  \
+                },\n  \
+                \"z\": 1\n\
+            }");
+    }
+
+    #[test]
+    fn test_write_numeric_looking_keys_quoted() {
+        use std::collections::BTreeMap;
+
+        let obj = mk_object(&[("123".to_string(), U64(1))]);
+        assert_eq!(obj.to_string(), r#"{"123":1}"#);
+        assert_eq!(obj.pretty().to_string(), "{\n  \"123\": 1\n}");
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_key_order(&["123"]);
+            obj.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, r#"{"123":1}"#);
+
+        let mut map = BTreeMap::new();
+        map.insert(123i32, true);
+        map.insert(-1, false);
+        assert_eq!(super::encode(&map).unwrap(), r#"{"-1":false,"123":true}"#);
+    }
+
+    #[test]
+    fn test_write_float_keys() {
+        use Encoder as EncoderTrait;
+
+        fn encode_key(key: f64) -> super::EncodeResult<string::String> {
+            let mut s = string::String::new();
+            try!(Encoder::new(&mut s).emit_map(1, |e| {
+                try!(e.emit_map_elt_key(0, |e| e.emit_f64(key)));
+                e.emit_map_elt_val(0, |e| e.emit_bool(true))
+            }));
+            Ok(s)
+        }
+
+        assert_eq!(encode_key(1.5), Ok(r#"{"1.5":true}"#.to_string()));
+        assert_eq!(encode_key(-2.0), Ok(r#"{"-2.0":true}"#.to_string()));
+        for &key in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY].iter() {
+            assert_eq!(encode_key(key), Err(EncoderError::BadHashmapKey));
+        }
+    }
+
+    #[test]
+    fn test_write_json5() {
+        use super::ParserConfig;
+
+        fn encode<T: Encodable>(t: &T, pretty: bool) -> string::String {
+            let mut s = string::String::new();
+            {
+                let mut encoder = if pretty { Encoder::new_pretty(&mut s) }
+                                  else { Encoder::new(&mut s) };
+                encoder.set_json5(true);
+                t.encode(&mut encoder).unwrap();
+            }
+            s
+        }
+
+        let obj = Json::from_str(r#"{"name": "x", "$id_2": [1, []], "1a": {},
+                                     "a b": {"c": null}}"#).unwrap();
+        assert_eq!(encode(&obj, false), r#"{$id_2:[1,[]],"1a":{},"a b":{c:null},name:"x"}"#);
+        assert_eq!(encode(&obj, true), "\
+            {\n  \
+                $id_2: [\n    \
+                    1,\n    \
+                    [],\n  \
+                ],\n  \
+                \"1a\": {},\n  \
+                \"a b\": {\n    \
+                    c: null,\n  \
+                },\n  \
+                name: \"x\",\n\
+            }");
+
+        let frog = Frog("Henry".to_string(), 349);
+        assert_eq!(encode(&frog, false), r#"{variant:"Frog",fields:["Henry",349]}"#);
+        assert_eq!(encode(&frog, true), "\
+            {\n  \
+                variant: \"Frog\",\n  \
+                fields: [\n    \
+                    \"Henry\",\n    \
+                    349,\n  \
+                ],\n\
+            }");
+        assert_eq!(encode(&Dog, true), "\"Dog\"");
+
+        // the output reads back with the JSON5 extensions the parser allows
+        let config = ParserConfig {
+            allow_trailing_commas: true,
+            allow_unquoted_keys: true,
+            ..ParserConfig::default()
+        };
+        for &pretty in &[false, true] {
+            assert_eq!(Json::from_str_with(&encode(&obj, pretty), &config), Ok(obj.clone()));
+            assert_eq!(Json::from_str_with(&encode(&frog, pretty), &config),
+                       Json::from_str(&super::encode(&frog).unwrap()));
+            let json = Json::from_str_with(&encode(&frog, pretty), &config).unwrap();
+            let decoded: Animal = Decodable::decode(&mut Decoder::new(json)).unwrap();
+            assert_eq!(decoded, frog);
+        }
+        assert_eq!(Json::from_str_with("{a1: 1, _: 2, $: 3}", &config).unwrap().to_string(),
+                   r#"{"$":3,"_":2,"a1":1}"#);
+        assert!(Json::from_str_with("{1a: 1}", &config).is_err());
+        assert!(Json::from_str("{a: 1}").is_err());
+    }
+
+    #[test]
+    fn test_emit_comment() {
+        use Encoder as EncoderTrait;
+
+        fn encode(json5: bool, pretty: bool) -> string::String {
+            let mut s = string::String::new();
+            {
+                let mut encoder = if pretty { Encoder::new_pretty(&mut s) }
+                                  else { Encoder::new(&mut s) };
+                encoder.set_json5(json5);
+                encoder.emit_comment("generated").unwrap();
+                encoder.emit_struct("Config", 2, |e| {
+                    try!(e.emit_comment("listen address"));
+                    try!(e.emit_struct_field("host", 0, |e| e.emit_str("::1")));
+                    try!(e.emit_comment("retries\nper request"));
+                    try!(e.emit_struct_field("retries", 1, |e| {
+                        e.emit_seq(1, |e| {
+                            try!(e.emit_seq_elt(0, |e| e.emit_u8(3)));
+                            e.emit_comment("end")
+                        })
+                    }));
+                    Ok(())
+                }).unwrap();
+            }
+            s
+        }
+
+        assert_eq!(encode(true, true), "\
+            // generated\n\
+            {\n  \
+                // listen address\n  \
+                host: \"::1\",\n  \
+                // retries\n  \
+                // per request\n  \
+                retries: [\n    \
+                    3,\n    \
+                    // end\n  \
+                ],\n\
+            }");
+        assert_eq!(encode(true, false), r#"{host:"::1",retries:[3]}"#);
+        assert_eq!(encode(false, false), r#"{"host":"::1","retries":[3]}"#);
+        assert_eq!(Json::from_str(&encode(false, true)),
+                   Json::from_str(&encode(false, false)));
+    }
+
     #[test]
     fn test_write_enum() {
         let animal = Dog;
@@ -2855,6 +5887,128 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_escape_unicode_scalar() {
+        fn escape(c: char) -> string::String {
+            let mut s = string::String::new();
+            super::escape_unicode_scalar(&mut s, c).unwrap();
+            s
+        }
+
+        assert_eq!(escape('\u{41}'), "\\u0041");
+        assert_eq!(escape('\u{e9}'), "\\u00e9");
+        assert_eq!(escape('\u{12ab}'), "\\u12ab");
+        assert_eq!(escape('\u{ffff}'), "\\uffff");
+        assert_eq!(escape('\u{10000}'), "\\ud800\\udc00");
+        assert_eq!(escape('\u{1f600}'), "\\ud83d\\ude00");
+        assert_eq!(escape('\u{10ffff}'), "\\udbff\\udfff");
+
+        for &c in &['\u{41}', '\u{e9}', '\u{12ab}', '\u{1f600}', '\u{10ffff}'] {
+            assert_eq!(Json::from_str(&format!("\"{}\"", escape(c))).unwrap(),
+                       String(c.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_write_escape_solidus() {
+        let value = mk_object(&[("a/b".to_string(), String("</script>".to_string()))]);
+        assert_eq!(value.to_string(), r#"{"a/b":"</script>"}"#);
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_escape_solidus(true);
+            value.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, r#"{"a\/b":"<\/script>"}"#);
+        assert!(!s.contains("</"));
+        assert_eq!(Json::from_str(&s).unwrap(), value);
+    }
+
+    #[test]
+    fn test_write_ascii_only() {
+        use Encoder as EncoderTrait;
+
+        fn encode<T: Encodable>(t: &T) -> string::String {
+            let mut s = string::String::new();
+            {
+                let mut encoder = Encoder::new(&mut s);
+                encoder.set_ascii_only(true);
+                t.encode(&mut encoder).unwrap();
+            }
+            s
+        }
+
+        let text = "caf\u{e9} \u{2603}\"\u{1F600}!\n".to_string();
+        let encoded = encode(&text);
+        assert_eq!(encoded, r#""caf\u00e9 \u2603\"\ud83d\ude00!\n""#);
+        assert!(encoded.is_ascii());
+        assert_eq!(super::decode::<string::String>(&encoded).unwrap(), text);
+        assert_eq!(super::encode(&text).unwrap(), "\"caf\u{e9} \u{2603}\\\"\u{1F600}!\\n\"");
+
+        assert_eq!(encode(&'\u{10FFFF}'), r#""\udbff\udfff""#);
+        let mut map = BTreeMap::new();
+        map.insert("\u{fc}ber".to_string(), "ok");
+        assert_eq!(encode(&map), r#"{"\u00fcber":"ok"}"#);
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_ascii_only(true);
+            encoder.emit_enum_variant("\u{e9}t\u{e9}", 0, 1, |e| {
+                e.emit_enum_variant_arg(0, |e| e.emit_u8(1))
+            }).unwrap();
+        }
+        assert_eq!(s, r#"{"variant":"\u00e9t\u00e9","fields":[1]}"#);
+    }
+
+    #[test]
+    fn test_write_enum_tagged() {
+        use Encoder;
+
+        enum Shape { Circle(u32) }
+        enum Color { Gray(u8) }
+
+        impl Encodable for Shape {
+            fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+                s.emit_enum("Shape", |s| match *self {
+                    Shape::Circle(r) => {
+                        s.emit_enum_variant_tagged("type", "value", "Circle", 0, 1, |s| {
+                            s.emit_enum_variant_arg(0, |s| r.encode(s))
+                        })
+                    }
+                })
+            }
+        }
+
+        impl Encodable for Color {
+            fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+                s.emit_enum("Color", |s| match *self {
+                    Color::Gray(v) => {
+                        s.emit_enum_variant_tagged("kind", "args", "Gray", 0, 1, |s| {
+                            s.emit_enum_variant_arg(0, |s| v.encode(s))
+                        })
+                    }
+                })
+            }
+        }
+
+        let value = (Shape::Circle(3), Color::Gray(128));
+        assert_eq!(
+            super::encode(&value).unwrap(),
+            "[{\"type\":\"Circle\",\"value\":[3]},{\"kind\":\"Gray\",\"args\":[128]}]"
+        );
+        assert_eq!(
+            format!("{}", super::as_pretty_json(&Shape::Circle(3))),
+            "{\n  \
+               \"type\": \"Circle\",\n  \
+               \"value\": [\n    \
+                 3\n  \
+               ]\n\
+             }"
+        );
+    }
+
     macro_rules! check_encoder_for_simple {
         ($value:expr, $expected:expr) => ({
             let s = format!("{}", super::as_json(&$value));
@@ -2887,6 +6041,38 @@ mod tests {
         check_encoder_for_simple!('\u{10ffff}', "\"\u{10ffff}\"");
     }
 
+    #[test]
+    fn test_write_number_str() {
+        use Encoder as EncoderTrait;
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.emit_seq(2, |e| {
+                try!(e.emit_seq_elt(0, |e| {
+                    e.emit_number_str("1.23456789012345678901234567890")
+                }));
+                e.emit_seq_elt(1, |e| e.emit_number_str("-1e400"))
+            }).unwrap();
+        }
+        assert_eq!(s, "[1.23456789012345678901234567890,-1e400]");
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.emit_map(1, |e| {
+                try!(e.emit_map_elt_key(0, |e| e.emit_number_str("10")));
+                e.emit_map_elt_val(0, |e| e.emit_number_str("0.5"))
+            }).unwrap();
+        }
+        assert_eq!(s, "{\"10\":0.5}");
+
+        assert!(super::is_number_str("-0.5e10"));
+        assert!(!super::is_number_str("1.2.3"));
+        assert!(!super::is_number_str("\"1\""));
+        assert!(!super::is_number_str(""));
+    }
+
     #[test]
     fn test_trailing_characters() {
         assert_eq!(Json::from_str("nulla"),  Err(SyntaxError(TrailingCharacters, 1, 5)));
@@ -2956,6 +6142,21 @@ mod tests {
         assert_eq!(Json::from_str("18446744073709551615"), Ok(U64(u64::MAX)));
     }
 
+    #[test]
+    fn test_read_number_huge_exponent() {
+        for &(s, v) in &[("1e999999999999999999999", f64::INFINITY),
+                         ("1E+999999999999999999999", f64::INFINITY),
+                         ("-1e999999999999999999999", f64::NEG_INFINITY),
+                         ("1e-999999999999999999999", 0.0),
+                         ("0e999999999999999999999", 0.0),
+                         ("0.5e-999999999999999999999", 0.0),
+                         ("1e400", f64::INFINITY),
+                         ("1e-400", 0.0),
+                         ("12.5e3", 12500.0)] {
+            assert_eq!(Json::from_str(s), Ok(F64(v)));
+        }
+    }
+
     #[test]
     fn test_decode_numbers() {
         let v: f64 = super::decode("3").unwrap();
@@ -3006,6 +6207,10 @@ mod tests {
     fn test_read_str() {
         assert_eq!(Json::from_str("\""),     Err(SyntaxError(EOFWhileParsingString, 1, 2)));
         assert_eq!(Json::from_str("\"lol"),  Err(SyntaxError(EOFWhileParsingString, 1, 5)));
+        assert_eq!(Json::from_str("\"\\"),  Err(SyntaxError(EOFWhileParsingEscape, 1, 3)));
+        assert_eq!(Json::from_str("\"\\u"), Err(SyntaxError(EOFWhileParsingEscape, 1, 4)));
+        assert_eq!(Json::from_str("\"\\u12"), Err(SyntaxError(EOFWhileParsingEscape, 1, 6)));
+        assert_eq!(Json::from_str("\"\\u12\""), Err(SyntaxError(InvalidEscape, 1, 6)));
         assert_eq!(Json::from_str("\"\n\""), Err(SyntaxError(ControlCharacterInString, 2, 1)));
         assert_eq!(Json::from_str("\"\0\""), Err(SyntaxError(ControlCharacterInString, 1, 2)));
         assert_eq!(Json::from_str("\"\u{1}\""), Err(SyntaxError(ControlCharacterInString, 1, 2)));
@@ -3087,6 +6292,144 @@ mod tests {
         assert_eq!(v, vec![vec![3], vec![1, 2]]);
     }
 
+    #[test]
+    fn test_encode_decode_cow() {
+        use std::borrow::Cow;
+
+        let borrowed: Cow<str> = Cow::Borrowed("a\"b");
+        let owned: Cow<[u8]> = Cow::Owned(vec![1, 2]);
+        assert_eq!(super::encode(&borrowed).unwrap(), "\"a\\\"b\"");
+        assert_eq!(super::encode(&owned).unwrap(), "[1,2]");
+
+        match super::decode::<Cow<str>>("\"a\\\"b\"").unwrap() {
+            Cow::Owned(s) => assert_eq!(s, "a\"b"),
+            Cow::Borrowed(_) => panic!("expected an owned value"),
+        }
+        let decoded: Cow<[u8]> = super::decode("[1,2]").unwrap();
+        assert_eq!(decoded, owned);
+    }
+
+    #[test]
+    fn test_encode_decode_fixed_array() {
+        let key = [7u8; 40];
+        let encoded = super::encode(&key).unwrap();
+        assert_eq!(Json::from_str(&encoded).unwrap().as_array().unwrap().len(), 40);
+        let decoded: [u8; 40] = super::decode(&encoded).unwrap();
+        assert_eq!(&decoded[..], &key[..]);
+
+        let v: [string::String; 2] = super::decode("[\"a\", \"b\"]").unwrap();
+        assert_eq!(v, ["a".to_string(), "b".to_string()]);
+
+        let empty: [u8; 0] = [];
+        assert_eq!(super::encode(&empty).unwrap(), "[]");
+        let _: [u8; 0] = super::decode("[]").unwrap();
+
+        assert_eq!(super::decode::<[u8; 0]>("[1]"),
+                   Err(ApplicationError("wrong array length".to_string())));
+        assert_eq!(super::decode::<[u8; 3]>("[1, 2]"),
+                   Err(ApplicationError("wrong array length".to_string())));
+    }
+
+    #[test]
+    fn test_read_enum_from_u64() {
+        #[derive(Debug, PartialEq)]
+        enum Level { Low = 1, High = 2 }
+
+        fn decode(json: Json) -> DecodeResult<Level> {
+            let mut decoder = Decoder::new(json);
+            decoder.read_enum_from_u64(&[(Level::Low as u64, 0),
+                                         (Level::High as u64, 1)], |_, idx| {
+                Ok(match idx {
+                    0 => Level::Low,
+                    1 => Level::High,
+                    _ => unreachable!(),
+                })
+            })
+        }
+
+        assert_eq!(decode(Json::U64(2)), Ok(Level::High));
+        assert_eq!(decode(Json::U64(1)), Ok(Level::Low));
+        assert_eq!(decode(Json::U64(99)),
+                   Err(UnexpectedVariantError("99".to_string(),
+                                              vec!["1".to_string(), "2".to_string()])));
+        assert_eq!(decode(Json::String("High".to_string())),
+                   Err(ExpectedError("Number".to_string(), "High".to_string())));
+    }
+
+    #[test]
+    fn test_read_struct_field_aliased() {
+        #[derive(Debug, PartialEq)]
+        struct User { email: string::String, age: Option<u8> }
+
+        fn decode(s: &str) -> DecodeResult<User> {
+            let mut decoder = Decoder::new(Json::from_str(s).unwrap());
+            ::Decoder::read_struct(&mut decoder, "User", 2, |d| {
+                Ok(User {
+                    email: try!(d.read_struct_field_aliased(&["emailAddress", "email"], 0,
+                                                            Decodable::decode)),
+                    age: try!(d.read_struct_field_aliased(&["age", "years"], 1,
+                                                          Decodable::decode)),
+                })
+            })
+        }
+
+        let user = User { email: "a@b.c".to_string(), age: Some(3) };
+        assert_eq!(decode(r#"{"email": "a@b.c", "years": 3}"#), Ok(user));
+        let user = User { email: "new@b.c".to_string(), age: None };
+        assert_eq!(decode(r#"{"email": "old@b.c", "emailAddress": "new@b.c"}"#), Ok(user));
+        assert_eq!(decode(r#"{"mail": "a@b.c"}"#),
+                   Err(MissingFieldError("emailAddress".to_string())));
+
+        let mut decoder = Decoder::new(Json::from_str(r#"{"a": 1}"#).unwrap());
+        let v: DecodeResult<u8> = ::Decoder::read_struct(&mut decoder, "A", 1, |d| {
+            d.read_struct_field_aliased(&[], 0, Decodable::decode)
+        });
+        assert!(match v { Err(ApplicationError(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn test_record_ignored_fields() {
+        let s = r#"{"inner": [{"a": null, "b": 2, "c": ["x"], "z": 1}], "extra": true}"#;
+        let mut decoder = Decoder::new(Json::from_str(s).unwrap());
+        decoder.set_record_ignored_fields(true);
+        let v: Outer = Decodable::decode(&mut decoder).unwrap();
+        assert_eq!(v.inner[0].b, 2);
+        assert_eq!(decoder.ignored_fields(), ["z".to_string(), "extra".to_string()]);
+
+        let mut decoder = Decoder::new(Json::from_str(s).unwrap());
+        let _: Outer = Decodable::decode(&mut decoder).unwrap();
+        assert!(decoder.ignored_fields().is_empty());
+    }
+
+    #[test]
+    fn test_decode_base64_bytes() {
+        let decode = |s: &str, base64: bool| {
+            let mut decoder = Decoder::new(Json::from_str(s).unwrap());
+            decoder.set_base64_bytes(base64);
+            let v: DecodeResult<Vec<u8>> = Decodable::decode(&mut decoder);
+            v
+        };
+
+        assert_eq!(decode("[1, 2, 255]", false), Ok(vec![1, 2, 255]));
+        assert_eq!(decode("[1, 2, 255]", true), Ok(vec![1, 2, 255]));
+        assert_eq!(decode("\"AQL/\"", true), Ok(vec![1, 2, 255]));
+        assert_eq!(decode("\"\"", true), Ok(vec![]));
+        assert_eq!(decode("\"AQL/\"", false),
+                   Err(ExpectedError("Array".to_string(), "\"AQL/\"".to_string())));
+        assert_eq!(decode("\"A$\"", true),
+                   Err(ExpectedError("Base64".to_string(), "A$".to_string())));
+
+        // only sequences of bytes are read from base64
+        let mut decoder = Decoder::new(Json::from_str("\"AQL/\"").unwrap());
+        decoder.set_base64_bytes(true);
+        let v: DecodeResult<Vec<u32>> = Decodable::decode(&mut decoder);
+        assert_eq!(v, Err(ExpectedError("Array".to_string(), "\"AQL/\"".to_string())));
+        let mut decoder = Decoder::new(Json::from_str("\"AQL/\"").unwrap());
+        decoder.set_base64_bytes(true);
+        let v: DecodeResult<Vec<string::String>> = Decodable::decode(&mut decoder);
+        assert!(v.is_err());
+    }
+
     #[test]
     fn test_decode_tuple() {
         let t: (usize, usize, usize) = super::decode("[1, 2, 3]").unwrap();
@@ -3222,6 +6565,50 @@ mod tests {
         assert_eq!(decoded_value, Ok(4));
     }
 
+    #[test]
+    fn test_decode_bound() {
+        use std::ops::Bound::{self, Included, Excluded, Unbounded};
+
+        for &(ref bound, json) in &[(Included(3), "{\"variant\":\"Included\",\"fields\":[3]}"),
+                                     (Excluded(-1), "{\"variant\":\"Excluded\",\"fields\":[-1]}"),
+                                     (Unbounded, "\"Unbounded\"")] {
+            assert_eq!(super::encode(bound).unwrap(), json);
+            let decoded: Bound<i32> = super::decode(json).unwrap();
+            assert_eq!(&decoded, bound);
+        }
+        assert!(super::decode::<Bound<i32>>("\"Included\"").is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_locks() {
+        use std::sync::{Arc, Mutex, RwLock};
+        use std::thread;
+
+        let mutex = Mutex::new(vec![1, 2, 3]);
+        assert_eq!(super::encode(&mutex).unwrap(), "[1,2,3]");
+        let decoded: Mutex<Vec<i32>> = super::decode("[1,2,3]").unwrap();
+        assert_eq!(*decoded.lock().unwrap(), vec![1, 2, 3]);
+
+        let rwlock = RwLock::new(Some("a".to_string()));
+        assert_eq!(super::encode(&rwlock).unwrap(), "\"a\"");
+        let decoded: RwLock<Option<string::String>> = super::decode("\"a\"").unwrap();
+        assert_eq!(*decoded.read().unwrap(), Some("a".to_string()));
+
+        let mutex = Arc::new(Mutex::new(1));
+        let rwlock = Arc::new(RwLock::new(1));
+        let (m, r) = (mutex.clone(), rwlock.clone());
+        let _ = thread::spawn(move || {
+            let _m = m.lock().unwrap();
+            let _r = r.write().unwrap();
+            panic!("poison the locks");
+        }).join();
+        assert!(mutex.is_poisoned() && rwlock.is_poisoned());
+        assert_eq!(super::encode(&*mutex),
+                   Err(EncoderError::ApplicationError("mutex is poisoned".to_string())));
+        assert_eq!(super::encode(&*rwlock),
+                   Err(EncoderError::ApplicationError("rwlock is poisoned".to_string())));
+    }
+
     #[test]
     fn test_decode_map() {
         let s = "{\"a\": \"Dog\", \"b\": {\"variant\":\"Frog\",\
@@ -3291,7 +6678,18 @@ mod tests {
         check_err::<DecodeEnum>("{\"variant\": \"A\", \"fields\": null}",
                                 ExpectedError("Array".to_string(), "null".to_string()));
         check_err::<DecodeEnum>("{\"variant\": \"C\", \"fields\": []}",
-                                UnknownVariantError("C".to_string()));
+                                UnexpectedVariantError("C".to_string(),
+                                                       vec!["A".to_string(), "B".to_string()]));
+        check_err::<DecodeEnum>("\"C\"",
+                                UnexpectedVariantError("C".to_string(),
+                                                       vec!["A".to_string(), "B".to_string()]));
+    }
+
+    #[test]
+    fn test_unknown_variant_error_display() {
+        let err = UnexpectedVariantError("C".to_string(),
+                                         vec!["A".to_string(), "B".to_string()]);
+        assert_eq!(err.to_string(), r#"unknown variant "C", expected one of "A", "B""#);
     }
 
     #[test]
@@ -3301,6 +6699,23 @@ mod tests {
         assert!(found_str.unwrap().as_string().unwrap() == "cat");
     }
 
+    #[test]
+    fn test_insert_remove() {
+        let mut json = Null;
+        assert_eq!(json.insert("a", U64(1)), None);
+        assert_eq!(json.insert("b", Boolean(true)), None);
+        assert_eq!(json.insert("a", U64(2)), Some(U64(1)));
+        assert_eq!(json, Json::from_str(r#"{"a": 2, "b": true}"#).unwrap());
+        assert_eq!(json.remove("b"), Some(Boolean(true)));
+        assert_eq!(json.remove("b"), None);
+        assert_eq!(json, Json::from_str(r#"{"a": 2}"#).unwrap());
+
+        let mut json = Array(vec![]);
+        assert_eq!(json.insert("a", U64(1)), None);
+        assert_eq!(json.remove("a"), None);
+        assert_eq!(json, Array(vec![]));
+    }
+
     #[test]
     fn test_find_path(){
         let json_value = Json::from_str("{\"dog\":{\"cat\": {\"mouse\" : \"cheese\"}}}").unwrap();
@@ -3315,6 +6730,28 @@ mod tests {
         assert!(found_str.unwrap() == "cheese");
     }
 
+    #[test]
+    fn test_contains_subset() {
+        let json = Json::from_str(
+            r#"{"id": 1, "user": {"name": "homura", "tags": ["a", "b", "c"]}, "ok": true}"#
+        ).unwrap();
+
+        let subset = Json::from_str(r#"{"user": {"tags": ["a", "b"]}, "ok": true}"#).unwrap();
+        assert!(json.contains_subset(&subset));
+        assert!(json.contains_subset(&json));
+        assert!(json.contains_subset(&mk_object(&[])));
+
+        let missing = Json::from_str(r#"{"user": {"email": "homura@example.com"}}"#).unwrap();
+        assert!(!json.contains_subset(&missing));
+
+        let mismatch = Json::from_str(r#"{"id": 2}"#).unwrap();
+        assert!(!json.contains_subset(&mismatch));
+
+        let too_long = Json::from_str(r#"{"user": {"tags": ["a", "b", "c", "d"]}}"#).unwrap();
+        assert!(!json.contains_subset(&too_long));
+        assert!(!U64(1).contains_subset(&String("1".to_string())));
+    }
+
     #[test]
     fn test_index(){
         let json_value = Json::from_str("{\"animals\":[\"dog\",\"cat\",\"mouse\"]}").unwrap();
@@ -3325,30 +6762,467 @@ mod tests {
     }
 
     #[test]
-    fn test_is_object(){
-        let json_value = Json::from_str("{}").unwrap();
-        assert!(json_value.is_object());
+    fn test_is_object(){
+        let json_value = Json::from_str("{}").unwrap();
+        assert!(json_value.is_object());
+    }
+
+    #[test]
+    fn test_as_object(){
+        let json_value = Json::from_str("{}").unwrap();
+        let json_object = json_value.as_object();
+        assert!(json_object.is_some());
+    }
+
+    #[test]
+    fn test_is_array(){
+        let json_value = Json::from_str("[1, 2, 3]").unwrap();
+        assert!(json_value.is_array());
+    }
+
+    #[test]
+    fn test_as_array(){
+        let json_value = Json::from_str("[1, 2, 3]").unwrap();
+        let json_array = json_value.as_array();
+        let expected_length = 3;
+        assert!(json_array.is_some() && json_array.unwrap().len() == expected_length);
+    }
+
+    #[test]
+    fn test_as_base64_and_hex_bytes() {
+        let json = Json::from_str(r#"{"b64": "aGVsbG8=", "hex": "68656c6c6f", "n": 1}"#).unwrap();
+
+        assert_eq!(json["b64"].as_base64_bytes().unwrap().unwrap(), b"hello");
+        assert_eq!(json["hex"].as_hex_bytes().unwrap().unwrap(), b"hello");
+        assert!(json["hex"].as_base64_bytes().unwrap().is_ok());
+        assert!(json["b64"].as_hex_bytes().unwrap().is_err());
+        assert!(String("a!".to_string()).as_base64_bytes().unwrap().is_err());
+        assert!(json["n"].as_base64_bytes().is_none());
+        assert!(json["n"].as_hex_bytes().is_none());
+    }
+
+    #[test]
+    fn test_get_path() {
+        let json = Json::from_str(r#"{
+            "a": {"b": [0, 1, {"c": "x"}]},
+            "servers": [{"host": "h0"}, {"host": "h1"}],
+            "": 1
+        }"#).unwrap();
+
+        assert_eq!(json.get_path("a.b[2].c"), Some(&String("x".to_string())));
+        assert_eq!(json.get_path("servers[1].host"), Some(&String("h1".to_string())));
+        assert_eq!(json.get_path("a.b[0]"), Some(&U64(0)));
+        assert_eq!(json.get_path(""), Some(&json));
+        assert_eq!(Array(vec![U64(7)]).get_path("[0]"), Some(&U64(7)));
+
+        assert_eq!(json.get_path("a.b[3]"), None);
+        assert_eq!(json.get_path("a.b[-1]"), None);
+        assert_eq!(json.get_path("a.b[1"), None);
+        assert_eq!(json.get_path("a[0]"), None);
+        assert_eq!(json.get_path("a.x"), None);
+        assert_eq!(json.get_path("servers[0]host"), None);
+        assert_eq!(json.get_path("a..b"), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        use super::{JsonSchema, SchemaError};
+
+        let mut props = BTreeMap::new();
+        props.insert("name".to_string(), JsonSchema::String);
+        props.insert("port".to_string(), JsonSchema::Number);
+        props.insert("tags".to_string(), JsonSchema::Array(Box::new(JsonSchema::String)));
+        let schema = JsonSchema::Object(props, vec!["name".to_string(), "port".to_string()]);
+
+        let good = Json::from_str(r#"{"name": "web", "port": 80, "tags": ["a"], "x": null}"#)
+            .unwrap();
+        assert_eq!(good.validate(&schema), Ok(()));
+        assert_eq!(good.validate(&JsonSchema::Any), Ok(()));
+
+        let bad = Json::from_str(r#"{"port": "80", "tags": ["a", 1, true]}"#).unwrap();
+        assert_eq!(bad.validate(&schema), Err(vec![
+            SchemaError::MissingKey("".to_string(), "name".to_string()),
+            SchemaError::TypeMismatch("/port".to_string(), "number", "string"),
+            SchemaError::TypeMismatch("/tags/1".to_string(), "string", "number"),
+            SchemaError::TypeMismatch("/tags/2".to_string(), "string", "boolean"),
+        ]));
+        assert_eq!(Json::Null.validate(&schema), Err(vec![
+            SchemaError::TypeMismatch("".to_string(), "object", "null"),
+        ]));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let resolve = |name: &str| match name {
+            "HOME" => Some("/home/me".to_string()),
+            _ => None,
+        };
+        let src = r#"{"dir": "${HOME}/data", "list": ["${HOME}${HOME}", "${USER}", "${HOME"],
+                      "n": 1}"#;
+        let mut json = Json::from_str(src).unwrap();
+        json.interpolate(&resolve);
+        assert_eq!(json, Json::from_str(r#"{"dir": "/home/me/data",
+            "list": ["/home/me/home/me", "${USER}", "${HOME"], "n": 1}"#).unwrap());
+
+        let mut json = Json::from_str(src).unwrap();
+        assert_eq!(json.try_interpolate(&resolve), Err("USER".to_string()));
+        assert_eq!(json, Json::from_str(src).unwrap());
+        let mut json = Json::from_str(r#"["${HOME}"]"#).unwrap();
+        assert_eq!(json.try_interpolate(&resolve), Ok(()));
+        assert_eq!(json, Json::from_str(r#"["/home/me"]"#).unwrap());
+    }
+
+    #[test]
+    fn test_summary() {
+        let json = Json::from_str(r#"{"a": 1, "b": [1, 2], "c": {}, "d": "x"}"#).unwrap();
+        assert_eq!(json.summary(80), r#"{a: 1, b: [...], c: {}, d: "x"}"#);
+        assert_eq!(json.summary(10), "{a: 1, b:…");
+        assert_eq!(json.summary(0), "");
+        assert_eq!(Array(vec![]).summary(2), "[]");
+        assert_eq!(String("héllo".to_string()).summary(4), "\"hé…");
+
+        let big = Array((0..10000).map(|i| mk_object(&[("n".to_string(), U64(i))])).collect());
+        let summary = big.summary(100);
+        assert_eq!(summary.chars().count(), 100);
+        assert!(summary.starts_with("[{...}, {...}, "));
+        assert!(summary.ends_with('\u{2026}'));
+    }
+
+    #[test]
+    fn test_sorted_pairs() {
+        let json = Json::from_str(r#"{"b": {"y": [true, null], "x": "s"}, "a/c": 1,
+                                      "e": [], "d": {}}"#).unwrap();
+        let pairs = json.to_sorted_pairs();
+        assert_eq!(pairs, vec![
+            ("/a~1c".to_string(), U64(1)),
+            ("/b/x".to_string(), String("s".to_string())),
+            ("/b/y/0".to_string(), Boolean(true)),
+            ("/b/y/1".to_string(), Null),
+            ("/d".to_string(), mk_object(&[])),
+            ("/e".to_string(), Array(vec![])),
+        ]);
+        assert_eq!(Json::from_sorted_pairs(pairs), Some(json));
+
+        assert_eq!(U64(1).to_sorted_pairs(), vec![("".to_string(), U64(1))]);
+        assert_eq!(Json::from_sorted_pairs(vec![("".to_string(), U64(1))]), Some(U64(1)));
+        assert_eq!(Json::from_sorted_pairs(vec![]), None);
+        assert_eq!(Json::from_sorted_pairs(vec![("/a".to_string(), U64(1)),
+                                                ("/a/b".to_string(), U64(2))]), None);
+        assert_eq!(Json::from_sorted_pairs(vec![("a".to_string(), U64(1))]), None);
+    }
+
+    #[test]
+    fn test_pointer() {
+        let json = Json::from_str(r#"{"a": [10, {"b/c": true, "d~e": null}], "": 1}"#).unwrap();
+        assert_eq!(json.pointer(""), Some(&json));
+        assert_eq!(json.pointer("/a/0"), Some(&U64(10)));
+        assert_eq!(json.pointer("/a/1/b~1c"), Some(&Boolean(true)));
+        assert_eq!(json.pointer("/a/1/d~0e"), Some(&Null));
+        assert_eq!(json.pointer("/"), Some(&U64(1)));
+        assert_eq!(json.pointer("/a/2"), None);
+        assert_eq!(json.pointer("/a/01"), None);
+        assert_eq!(json.pointer("/a/+1"), None);
+        assert_eq!(json.pointer("/a/0/x"), None);
+        assert_eq!(json.pointer("a"), None);
+
+        let default = U64(8080);
+        assert_eq!(json.pointer_or("/a/0", &default), &U64(10));
+        assert_eq!(json.pointer_or("/server/port", &default), &default);
+
+        let mut json = json;
+        *json.pointer_mut("/a/1/b~1c").unwrap() = Boolean(false);
+        json.pointer_mut("/a").unwrap().as_array_mut().unwrap().push(Null);
+        assert_eq!(json.pointer("/a/1/b~1c"), Some(&Boolean(false)));
+        assert_eq!(json.pointer("/a/2"), Some(&Null));
+        assert!(json.pointer_mut("/a/01").is_none());
+        assert!(json.pointer_mut("/a/0/x").is_none());
+        assert!(json.pointer_mut("a").is_none());
+        *json.pointer_mut("").unwrap() = Null;
+        assert_eq!(json, Null);
+    }
+
+    #[test]
+    fn test_serialized_len() {
+        use std::f64;
+
+        let docs = [r#"{"a": [1, -2, 3.25, 1e300, null, true], "b\"\n": "caf\u00e9 \u0007 \ud83d\ude00"}"#,
+                    r#"[[], {}, "", "\\/", 18446744073709551615, -9223372036854775808]"#,
+                    r#"{"nested": {"x": [{"y": "\u007f\u0000"}]}, "": 0.5}"#,
+                    "0", "\"\""];
+        for doc in docs.iter() {
+            let json = Json::from_str(doc).unwrap();
+            assert_eq!(json.serialized_len(), json.to_string().len());
+        }
+        for json in &[F64(f64::NAN), F64(-0.0), F64(1.0), Array(vec![F64(f64::INFINITY)])] {
+            assert_eq!(json.serialized_len(), json.to_string().len());
+        }
+        assert_eq!(String("\u{e9}\t".to_string()).serialized_len(), 6);
+    }
+
+    #[test]
+    fn test_clone_at() {
+        let mut json = Json::from_str(r#"{"a": {"b": [1, 2]}}"#).unwrap();
+        let mut sub = json.clone_at("/a").unwrap();
+        assert_eq!(Some(&sub), json.pointer("/a"));
+        assert_eq!(json.clone_at("/a/c"), None);
+
+        json.as_object_mut().unwrap().remove("a");
+        assert_eq!(json, mk_object(&[]));
+        assert_eq!(sub.pointer("/b/1"), Some(&U64(2)));
+
+        sub.as_object_mut().unwrap().insert("c".to_string(), Null);
+        let json = Json::from_str(r#"{"a": {"b": [1, 2]}}"#).unwrap();
+        assert!(json.clone_at("/a") != Some(sub));
+
+        let owned = Json::from_str("[[true]]").unwrap().clone_at("/0/0");
+        assert_eq!(owned, Some(Boolean(true)));
+    }
+
+    #[test]
+    fn test_leaf_pointers() {
+        let json = Json::from_str(r#"{
+            "a": [1, {"b": null, "c/d": true}, []],
+            "e~f": "g",
+            "h": {}
+        }"#).unwrap();
+        assert_eq!(json.leaf_pointers(), vec!["/a/0", "/a/1/b", "/a/1/c~1d", "/e~0f"]);
+        assert_eq!(U64(1).leaf_pointers(), vec![""]);
+        assert!(Array(vec![]).leaf_pointers().is_empty());
+    }
+
+    #[test]
+    fn test_eq_null_as_absent() {
+        let json = |s| Json::from_str(s).unwrap();
+
+        assert!(json(r#"{"a": null}"#).eq_null_as_absent(&json("{}")));
+        assert!(json("{}").eq_null_as_absent(&json(r#"{"a": null}"#)));
+        assert!(json(r#"{"a": 1, "b": null}"#).eq_null_as_absent(&json(r#"{"a": 1}"#)));
+        assert!(json(r#"[{"a": {"b": null}}]"#).eq_null_as_absent(&json(r#"[{"a": {}}]"#)));
+        assert!(json(r#"{"a": null}"#) != json("{}"));
+
+        assert!(!json(r#"{"a": 1}"#).eq_null_as_absent(&json("{}")));
+        assert!(!json(r#"{"a": null}"#).eq_null_as_absent(&json(r#"{"a": 1}"#)));
+        assert!(!json("[null]").eq_null_as_absent(&json("[]")));
+        assert!(!json(r#"{"a": [1]}"#).eq_null_as_absent(&json(r#"{"a": [2]}"#)));
+    }
+
+    #[test]
+    fn test_eq_ignoring() {
+        let json = |s| Json::from_str(s).unwrap();
+
+        let a = json(r#"{"id": 1, "at": "10:00", "items": [{"id": 7, "n": 1}], "x/y": 0}"#);
+        let b = json(r#"{"id": 2, "items": [{"id": 8, "n": 1}], "x/y": 1}"#);
+        assert!(a.eq_ignoring(&b, &["/id", "/at", "/items/0/id", "/x~1y"]));
+        assert!(b.eq_ignoring(&a, &["/id", "/at", "/items/0/id", "/x~1y"]));
+        assert!(!a.eq_ignoring(&b, &["/id", "/at", "/items/0/id"]));
+        assert!(!a.eq_ignoring(&b, &["/id", "/items/0/id", "/x~1y"]));
+        assert!(a.eq_ignoring(&b, &["/id", "/at", "/items", "/x~1y"]));
+        assert!(a.eq_ignoring(&json("null"), &[""]));
+
+        assert!(json("[1, 2]").eq_ignoring(&json("[1]"), &["/1"]));
+        assert!(!json("[1, 2]").eq_ignoring(&json("[1]"), &["/0"]));
+        assert!(json("[1, 2]").eq_ignoring(&json("[1, 2]"), &[]));
+    }
+
+    #[test]
+    fn test_total_cmp() {
+        use std::cmp::Ordering;
+
+        let mut values = vec![
+            String("b".to_string()), F64(f64::NAN), U64(3), F64(1.5),
+            I64(-2), Null, F64(-f64::INFINITY), String("a".to_string()),
+            F64(-0.0), F64(0.0), Array(vec![F64(f64::NAN)]), Array(vec![]),
+        ];
+        values.sort_by(|a, b| a.total_cmp(b));
+        let sorted = format!("{:?}", values);
+        assert_eq!(sorted, format!("{:?}", vec![
+            I64(-2), U64(3), F64(-f64::INFINITY), F64(-0.0), F64(0.0), F64(1.5),
+            F64(f64::NAN), String("a".to_string()), String("b".to_string()),
+            Array(vec![]), Array(vec![F64(f64::NAN)]), Null,
+        ]));
+
+        let nan = F64(f64::NAN);
+        assert_eq!(nan.total_cmp(&nan), Ordering::Equal);
+        assert_eq!(mk_object(&[("a".to_string(), U64(1))])
+                       .total_cmp(&mk_object(&[("a".to_string(), U64(2))])),
+                   Ordering::Less);
+        assert_eq!(mk_object(&[("a".to_string(), U64(1))])
+                       .total_cmp(&mk_object(&[("b".to_string(), U64(0))])),
+                   Ordering::Less);
+    }
+
+    #[test]
+    fn test_ord() {
+        use std::cmp::Ordering;
+
+        let mut values = vec![
+            String("b".to_string()), F64(f64::NAN), U64(3), F64(1.5), Boolean(false),
+            I64(-2), Null, F64(-f64::INFINITY), String("a".to_string()), Boolean(true),
+            F64(0.0), Array(vec![F64(f64::NAN)]), Array(vec![]), I64(-5),
+        ];
+        values.sort();
+        assert_eq!(format!("{:?}", values), format!("{:?}", vec![
+            Null, Boolean(false), Boolean(true), F64(-f64::INFINITY), I64(-5), I64(-2),
+            F64(0.0), F64(1.5), U64(3), F64(f64::NAN), String("a".to_string()),
+            String("b".to_string()), Array(vec![]), Array(vec![F64(f64::NAN)]),
+        ]));
+
+        assert_eq!(F64(-0.0).cmp(&F64(0.0)), Ordering::Equal);
+        assert_eq!(F64(f64::NAN).cmp(&F64(-f64::NAN)), Ordering::Equal);
+        assert_eq!(F64(f64::NAN), F64(-f64::NAN));
+        assert_eq!(Array(vec![F64(f64::NAN)]), Array(vec![F64(f64::NAN)]));
+        assert_eq!(F64(f64::NAN).partial_cmp(&F64(f64::INFINITY)), Some(Ordering::Greater));
+
+        // numbers compare by value across variants, then by variant
+        assert!(U64(0) < I64(1));
+        assert!(I64(-1) < U64(0));
+        assert!(I64(1) < U64(1) && U64(1) < F64(1.0));
+        assert!(F64(0.5) < I64(1) && I64(1) < F64(1.5));
+        assert!(F64(-1.5) < I64(-1) && I64(-1) < F64(-0.5));
+        assert!(U64(u64::MAX) < F64(18446744073709551616.0));
+        assert!(I64(i64::MAX) < F64(9223372036854775808.0));
+        assert!(I64(i64::MIN) > F64(-1e19));
+        assert_eq!(I64(i64::MIN).cmp(&F64(-9223372036854775808.0)), Ordering::Less);
+        assert!(U64(1 << 53) < U64((1 << 53) + 1));
+        assert!(F64(9007199254740992.0) < U64((1 << 53) + 1));
+        assert!(I64(-9007199254740993) < F64(-9007199254740992.0));
+        assert!(I64(0) < U64(0) && U64(0) < F64(-0.0));
+        assert!(I64(i64::MAX) < F64(f64::NAN) && U64(u64::MAX) < F64(f64::NAN));
+        assert!(mk_object(&[("a".to_string(), U64(1))]) < mk_object(&[("a".to_string(), U64(2))]));
+        assert!(mk_object(&[("a".to_string(), U64(1))]) < mk_object(&[("b".to_string(), U64(0))]));
+    }
+
+    #[test]
+    fn test_to_canonical_vec() {
+        use std::str;
+
+        let mut json = Json::from_str(r#"{
+            "a": {"z": null, "y": false},
+            "\ud800\udc00": 0,
+            "\ue000": 1
+        }"#).unwrap();
+        json.as_object_mut().unwrap().insert("b".to_string(), Array(vec![
+            U64(1), I64(-2), F64(2.5), F64(1e21), F64(1e-7), F64(0.000001),
+            F64(123456789012.5), F64(-0.0), String("\u{20ac}\u{1}\n\u{7f}".to_string()),
+        ]));
+
+        let bytes = json.to_canonical_vec().unwrap();
+        assert_eq!(str::from_utf8(&bytes).unwrap(),
+                   "{\"a\":{\"y\":false,\"z\":null},\
+                     \"b\":[1,-2,2.5,1e+21,1e-7,0.000001,123456789012.5,0,\"\u{20ac}\\u0001\\n\u{7f}\"],\
+                     \"\u{10000}\":0,\"\u{e000}\":1}");
+
+        let json = Json::from_str(r#"{"b": [1, -2, 2.5, "\u00e9\u0002"], "a": {"c": [null, true]}}"#)
+            .unwrap();
+        let bytes = json.to_canonical_vec().unwrap();
+        assert_eq!(Json::from_str(str::from_utf8(&bytes).unwrap()).unwrap(), json);
+    }
+
+    #[test]
+    fn test_hash_set() {
+        use std::collections::HashSet;
+        use std::hash::{Hash, Hasher};
+        use std::collections::hash_map::DefaultHasher;
+
+        fn hash(json: &Json) -> u64 {
+            let mut h = DefaultHasher::new();
+            json.hash(&mut h);
+            h.finish()
+        }
+
+        let docs = [r#"{"a": [1, 2.5], "b": null}"#, r#"{"b": null, "a": [1, 2.5]}"#,
+                    r#"{"a": [1, 2.5]}"#, "[1, 2.5]", "1", "-1", "\"1\"", "true"];
+        let set: HashSet<Json> = docs.iter().map(|d| Json::from_str(d).unwrap()).collect();
+        assert_eq!(set.len(), docs.len() - 1);
+        assert!(set.contains(&Json::from_str(r#"{"b": null, "a": [1, 2.5]}"#).unwrap()));
+        assert!(set.contains(&U64(1)));
+        assert!(!set.contains(&I64(1)));
+
+        assert_eq!(F64(0.0), F64(-0.0));
+        assert_eq!(hash(&F64(0.0)), hash(&F64(-0.0)));
+        assert_eq!(hash(&F64(f64::NAN)), hash(&F64(-f64::NAN)));
+        assert!(hash(&U64(1)) != hash(&I64(1)));
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        let hash = |s| Json::from_str(s).unwrap().stable_hash();
+
+        assert_eq!(hash(r#"{"a": 1, "b": [true, null]}"#),
+                   hash(r#"{"b": [true, null], "a": 1}"#));
+        assert_eq!(I64(1).stable_hash(), U64(1).stable_hash());
+        assert_eq!(I64(1).stable_hash(), F64(1.0).stable_hash());
+        assert_eq!(I64(-3).stable_hash(), F64(-3.0).stable_hash());
+        assert_eq!(F64(0.0).stable_hash(), F64(-0.0).stable_hash());
+        assert_eq!(F64(f64::NAN).stable_hash(), F64(-f64::NAN).stable_hash());
+        assert_eq!(hash("[1, 2.0]"), hash("[1.0, 2]"));
+
+        assert!(hash(r#"{"a": 1}"#) != hash(r#"{"a": 2}"#));
+        assert!(hash(r#"{"a": 1}"#) != hash(r#"{"b": 1}"#));
+        assert!(hash(r#"["ab", "c"]"#) != hash(r#"["a", "bc"]"#));
+        assert!(hash("[[1], 2]") != hash("[[1, 2]]"));
+        assert!(hash("1") != hash("\"1\""));
+        assert!(hash("1.5") != hash("1"));
+        assert!(hash("-1") != hash("18446744073709551615"));
+        assert!(hash("true") != hash("false"));
+    }
+
+    #[test]
+    fn test_map_strings() {
+        let mut json = Json::from_str(
+            r#"{"Name": "ALICE", "tags": ["Admin", {"Role": "OWNER"}], "age": 30}"#).unwrap();
+        json.map_strings(|s| *s = s.to_lowercase());
+        assert_eq!(json, Json::from_str(
+            r#"{"Name": "alice", "tags": ["admin", {"Role": "owner"}], "age": 30}"#).unwrap());
+    }
+
+    #[test]
+    fn test_map_numbers() {
+        let mut json = Json::from_str(r#"{"a": 1, "b": [-2, 3.5, "4"], "c": null}"#).unwrap();
+        json.map_numbers(|n| *n = F64(n.as_f64().unwrap() * 2.0));
+        assert_eq!(json, Json::from_str(r#"{"a": 2.0, "b": [-4.0, 7.0, "4"], "c": null}"#).unwrap());
     }
 
     #[test]
-    fn test_as_object(){
-        let json_value = Json::from_str("{}").unwrap();
-        let json_object = json_value.as_object();
-        assert!(json_object.is_some());
+    fn test_take() {
+        let mut json = Json::from_str(r#"{"a": [1, [2]], "b": null}"#).unwrap();
+        let inner = json.pointer_mut("/a/1").unwrap().take();
+        assert_eq!(inner, Array(vec![U64(2)]));
+        assert_eq!(json["a"], Array(vec![U64(1), Null]));
+        assert_eq!(json.take(), Json::from_str(r#"{"a": [1, null], "b": null}"#).unwrap());
+        assert_eq!(json, Null);
     }
 
     #[test]
-    fn test_is_array(){
-        let json_value = Json::from_str("[1, 2, 3]").unwrap();
-        assert!(json_value.is_array());
+    fn test_merge() {
+        let mut json = Json::from_str(r#"{"a": 1, "b": {"c": [1, 2], "d": true}, "e": {}}"#)
+            .unwrap();
+        json.merge(Json::from_str(r#"{"b": {"c": [3], "f": null}, "e": 2, "g": {"h": 1}}"#)
+            .unwrap());
+        assert_eq!(json, Json::from_str(r#"{"a": 1, "b": {"c": [3], "d": true, "f": null},
+                                            "e": 2, "g": {"h": 1}}"#).unwrap());
+
+        json.merge(Array(vec![]));
+        assert_eq!(json, Array(vec![]));
+        json.merge(Json::from_str(r#"{"a": 1}"#).unwrap());
+        assert_eq!(json, Json::from_str(r#"{"a": 1}"#).unwrap());
     }
 
     #[test]
-    fn test_as_array(){
-        let json_value = Json::from_str("[1, 2, 3]").unwrap();
-        let json_array = json_value.as_array();
-        let expected_length = 3;
-        assert!(json_array.is_some() && json_array.unwrap().len() == expected_length);
+    fn test_into_array_iter(){
+        let json_value = Json::from_str("[1, \"two\", [3]]").unwrap();
+        let values: Vec<Json> = json_value.into_array_iter().collect();
+        assert_eq!(values, vec![U64(1), String("two".to_string()), Array(vec![U64(3)])]);
+        assert_eq!(Json::from_str("{}").unwrap().into_array_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_into_object_iter(){
+        let json_value = Json::from_str("{\"a\": 1, \"b\": [2]}").unwrap();
+        let pairs: Vec<(string::String, Json)> = json_value.into_object_iter().collect();
+        assert_eq!(pairs, vec![("a".to_string(), U64(1)),
+                               ("b".to_string(), Array(vec![U64(2)]))]);
+        assert_eq!(Json::from_str("[1]").unwrap().into_object_iter().count(), 0);
     }
 
     #[test]
@@ -3537,6 +7411,106 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_prettyencoder_indent_str() {
+        let json = Json::from_str(r#"{"a": [1, {"b": null}]}"#).unwrap();
+        assert_eq!(super::as_pretty_json(&json).indent_str("\t").to_string(),
+                   "{\n\t\"a\": [\n\t\t1,\n\t\t{\n\t\t\t\"b\": null\n\t\t}\n\t]\n}");
+        assert_eq!(super::as_pretty_json(&json).indent_str("\t").indent(2).to_string(),
+                   super::as_pretty_json(&json).to_string());
+        assert_eq!(super::as_pretty_json(&json).indent_str("    ").to_string(),
+                   super::as_pretty_json(&json).indent(4).to_string());
+        assert_eq!(super::as_pretty_json(&Array(vec![U64(1)])).indent_str("\t").at_depth(2)
+                       .to_string(),
+                   "[\n\t\t\t1\n\t\t]");
+
+        // switching part way through keeps the depth
+        let mut s = string::String::new();
+        {
+            use Encoder as EncoderTrait;
+            let mut encoder = Encoder::new_pretty(&mut s);
+            encoder.emit_seq(2, |e| {
+                try!(e.emit_seq_elt(0, |e| e.emit_u8(1)));
+                e.set_indent_str("\t").unwrap();
+                e.emit_seq_elt(1, |e| e.emit_seq(1, |e| e.emit_seq_elt(0, |e| e.emit_u8(2))))
+            }).unwrap();
+            assert!(Encoder::new(&mut string::String::new()).set_indent_str("\t").is_err());
+        }
+        assert_eq!(s, "[\n  1,\n\t[\n\t\t2\n\t]\n]");
+    }
+
+    #[test]
+    fn test_prettyencoder_align_values() {
+        let json = Json::from_str(r#"{"a": 1, "ccc": {"bb": [true], "d": {}}, "é": "x"}"#)
+            .unwrap();
+        assert_eq!(super::as_pretty_json(&json).align_values(true).to_string(),
+                   "{\n  \"a\":   1,\n  \"ccc\": {\n    \"bb\": [\n      true\n    ],\n    \
+                    \"d\":  {}\n  },\n  \"é\":   \"x\"\n}");
+        assert_eq!(super::as_pretty_json(&json).align_values(false).to_string(),
+                   super::as_pretty_json(&json).to_string());
+
+        // struct fields are aligned too, and kept in order
+        let animal = Frog("Henry".to_string(), 349);
+        let s = super::as_pretty_json(&vec![animal]).align_values(true).to_string();
+        assert_eq!(s, "[\n  {\n    \"variant\": \"Frog\",\n    \"fields\":  [\n      \
+                       \"Henry\",\n      349\n    ]\n  }\n]");
+
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_align_values(true);
+            json.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, json.to_string());
+    }
+
+    #[test]
+    fn test_prettyencoder_at_depth() {
+        let json = Array(vec![U64(1), Array(vec![U64(2)])]);
+
+        assert_eq!(super::as_pretty_json(&json).at_depth(0).to_string(),
+                   super::as_pretty_json(&json).to_string());
+        assert_eq!(
+            super::as_pretty_json(&json).at_depth(2).to_string(),
+            "\
+            [\n      \
+                1,\n      \
+                [\n        \
+                    2\n      \
+                ]\n    \
+            ]"
+        );
+        assert_eq!(
+            super::as_pretty_json(&json).indent(1).at_depth(3).to_string(),
+            "[\n    1,\n    [\n     2\n    ]\n   ]"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "color")]
+    fn test_prettyencoder_colored() {
+        let json = Json::from_str(r#"{"a": [1, "b", true, null]}"#).unwrap();
+        assert_eq!(
+            super::as_pretty_json(&json).colored().to_string(),
+            "{\n  \
+                \x1b[34m\"a\"\x1b[0m: [\n    \
+                    \x1b[36m1\x1b[0m,\n    \
+                    \x1b[32m\"b\"\x1b[0m,\n    \
+                    \x1b[35mtrue\x1b[0m,\n    \
+                    \x1b[35mnull\x1b[0m\n  \
+                ]\n\
+            }"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "color"))]
+    fn test_prettyencoder_colored_disabled() {
+        let json = Json::from_str(r#"{"a": [1, "b", true, null]}"#).unwrap();
+        assert_eq!(super::as_pretty_json(&json).colored().to_string(),
+                   super::as_pretty_json(&json).to_string());
+    }
+
     #[test]
     fn test_hashmap_with_numeric_key_can_handle_double_quote_delimited_key() {
         use std::collections::HashMap;
@@ -3568,6 +7542,122 @@ mod tests {
         assert_eq!(map, decoded);
     }
 
+    #[test]
+    fn test_hashmap_with_struct_key_from_pairs() {
+        use std::collections::HashMap;
+        #[derive(PartialEq, Eq, Hash, RustcDecodable, Debug)]
+        struct Point { x: i32, y: i32 }
+
+        let decoded: HashMap<Point, string::String> =
+            super::decode(r#"[[{"x":1,"y":2},"a"],[{"x":-1,"y":0},"b"]]"#).unwrap();
+        let mut expected = HashMap::new();
+        expected.insert(Point { x: 1, y: 2 }, "a".to_string());
+        expected.insert(Point { x: -1, y: 0 }, "b".to_string());
+        assert_eq!(decoded, expected);
+
+        let empty: HashMap<Point, bool> = super::decode("[]").unwrap();
+        assert!(empty.is_empty());
+        let result: DecodeResult<HashMap<Point, bool>> = super::decode("[[1]]");
+        assert_eq!(result, Err(ExpectedError("Array of [key, value] pairs".to_string(),
+                                             "[1]".to_string())));
+    }
+
+    #[test]
+    fn test_encode_hashmap_sort_keys() {
+        use std::collections::HashMap;
+
+        let encode = |map: &HashMap<string::String, u32>| {
+            let mut s = string::String::new();
+            {
+                let mut encoder = Encoder::new(&mut s);
+                encoder.set_sort_keys(true);
+                map.encode(&mut encoder).unwrap();
+            }
+            s
+        };
+        // maps built with different random seeds and insertion orders
+        let keys = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let mut first = HashMap::new();
+        for (i, k) in keys.iter().enumerate() {
+            first.insert(k.to_string(), i as u32);
+        }
+        let expected = encode(&first);
+        for _ in 0..10 {
+            let mut map = HashMap::new();
+            for (i, k) in keys.iter().enumerate().rev() {
+                map.insert(k.to_string(), i as u32);
+            }
+            assert_eq!(encode(&map), expected);
+        }
+        assert_eq!(expected, r#"{"a":0,"b":1,"c":2,"d":3,"e":4,"f":5,"g":6,"h":7}"#);
+        assert_eq!(Json::from_str(&expected).unwrap(),
+                   Json::from_str(&super::encode(&first).unwrap()).unwrap());
+
+        // any map is sorted, including nested ones and those with other keys
+        let mut nested: HashMap<u32, HashMap<string::String, u32>> = HashMap::new();
+        for i in &[10, 9, 100, 1] {
+            nested.insert(*i, first.clone());
+        }
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new_pretty(&mut s);
+            encoder.set_sort_keys(true);
+            nested.encode(&mut encoder).unwrap();
+        }
+        let json = Json::from_str(&s).unwrap();
+        assert_eq!(json.as_object().unwrap().len(), 4);
+        let keys: Vec<&str> = s.lines().filter(|l| l.starts_with("  \""))
+                               .map(|l| l.trim()).collect();
+        assert_eq!(keys, vec!["\"1\": {", "\"10\": {", "\"100\": {", "\"9\": {"]);
+        assert!(s.contains("{\n    \"a\": 0,\n    \"b\": 1,"));
+
+        // keys are compared before they are quoted and escaped
+        let mut map = HashMap::new();
+        for k in &["b", "a b", "a", "\n"] {
+            map.insert(k.to_string(), 0);
+        }
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_json5(true);
+            encoder.set_sort_keys(true);
+            map.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, r#"{"\n":0,a:0,"a b":0,b:0}"#);
+
+        let mut map = HashMap::new();
+        for (i, c) in "hgfedcba".chars().enumerate() {
+            map.insert(c, i);
+        }
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_sort_keys(true);
+            map.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, r#"{"a":7,"b":6,"c":5,"d":4,"e":3,"f":2,"g":1,"h":0}"#);
+    }
+
+    #[test]
+    fn test_decode_map_pairs_duplicate_keys() {
+        use std::collections::BTreeMap;
+
+        let decode = |s: &str, strict: bool| {
+            let mut decoder = Decoder::new(Json::from_str(s).unwrap());
+            decoder.set_reject_duplicate_keys(strict);
+            let map: DecodeResult<BTreeMap<u32, bool>> = Decodable::decode(&mut decoder);
+            map
+        };
+
+        let src = "[[1, true], [2, false], [1, false]]";
+        let mut expected = BTreeMap::new();
+        expected.insert(1, false);
+        expected.insert(2, false);
+        assert_eq!(decode(src, false), Ok(expected.clone()));
+        assert_eq!(decode(src, true), Err(DuplicateKeyError("1".to_string())));
+        assert_eq!(decode("[[1, false], [2, false]]", true), Ok(expected));
+    }
+
     #[test]
     fn test_hashmap_with_numeric_key_will_error_with_string_keys() {
         use std::collections::HashMap;
@@ -3814,6 +7904,448 @@ mod tests {
         assert_eq!(last_event("faz"),  Error(SyntaxError(InvalidSyntax, 1, 3)));
     }
 
+    #[test]
+    fn test_parser_config_default_is_strict() {
+        use super::ParserConfig;
+
+        let config = ParserConfig::default();
+        for src in &["[1, 2]", "{\"a\": null}", "[1, 2,]", "{\"a\": 1,}",
+                     "[1] // comment", "/* comment */ 1", "NaN", "[-Infinity]"] {
+            assert_eq!(Json::from_str_with(src, &config), Json::from_str(src));
+            let mut reader = src.as_bytes();
+            assert_eq!(Json::from_reader_with(&mut reader, &config), Json::from_str(src));
+        }
+        assert!(Json::from_str_with("[1, 2,]", &config).is_err());
+
+        let parser = Parser::with_config("[]".chars(), config.clone());
+        assert_eq!(parser.config(), &config);
+    }
+
+    #[test]
+    fn test_reject_duplicate_keys() {
+        use super::{ParserConfig, DuplicateKey};
+
+        let config = ParserConfig { reject_duplicate_keys: true, ..ParserConfig::default() };
+        let src = r#"{"a": {"b": 1, "c": 2}, "b": {"b": 3, "b": 4}}"#;
+
+        let events: Vec<JsonEvent> = Parser::with_config(src.chars(), config.clone()).collect();
+        assert_eq!(events.last(), Some(&Error(SyntaxError(DuplicateKey, 1, 42))));
+        assert_eq!(events.len(), 8);
+
+        // Keys are only compared within the same object
+        let src = r#"{"a": {"a": {"a": 1}}, "b": [{"a": 1}, {"a": 2}]}"#;
+        let mut parser = Parser::with_config(src.chars(), config.clone());
+        assert!(parser.all(|event| match event { Error(_) => false, _ => true }));
+
+        assert_eq!(Json::from_str_with(r#"{"a": 1, "a": 2}"#, &config),
+                   Err(SyntaxError(DuplicateKey, 1, 13)));
+        assert_eq!(Json::from_str(r#"{"a": 1, "a": 2}"#),
+                   Ok(mk_object(&[("a".to_string(), U64(2))])));
+    }
+
+    #[test]
+    fn test_allow_comments() {
+        use super::{ParserConfig, EOFWhileParsingComment};
+
+        let config = ParserConfig { allow_comments: true, ..ParserConfig::default() };
+        let src = "// leading\n{\"a\": /* inline */ 1, // trailing\n \"b\" /**/ : \"/* kept */ // kept\"\
+                   /* multi\n * line **/}\n// at eof";
+        assert_eq!(Json::from_str_with(src, &config),
+                   Ok(mk_object(&[("a".to_string(), U64(1)),
+                                  ("b".to_string(), String("/* kept */ // kept".to_string()))])));
+        assert_eq!(Json::from_str_with("[1] // no newline", &config),
+                   Ok(Array(vec![U64(1)])));
+
+        assert_eq!(Json::from_str_with("[1 /* open", &config),
+                   Err(SyntaxError(EOFWhileParsingComment, 1, 11)));
+        assert_eq!(Json::from_str_with("[1] /* open *", &config),
+                   Err(SyntaxError(EOFWhileParsingComment, 1, 14)));
+        assert_eq!(Json::from_str_with("[1, / 2]", &config),
+                   Err(SyntaxError(InvalidSyntax, 1, 6)));
+        assert_eq!(Json::from_str("[1, /* c */ 2]"),
+                   Err(SyntaxError(InvalidSyntax, 1, 5)));
+
+        let mut builder = super::Builder::with_config("1 /* a */ 2 /* b".chars(), config);
+        assert_eq!(builder.build_one(), Ok(Some(U64(1))));
+        assert_eq!(builder.build_one(), Ok(Some(U64(2))));
+        assert_eq!(builder.build_one(), Err(SyntaxError(EOFWhileParsingComment, 1, 17)));
+    }
+
+    #[test]
+    fn test_allow_trailing_commas() {
+        use super::{ParserConfig, StackElement};
+
+        let config = ParserConfig { allow_trailing_commas: true, ..ParserConfig::default() };
+        for &(relaxed, strict) in &[("[1, 2,]", "[1, 2]"),
+                                    ("{\"a\": 1,}", "{\"a\": 1}"),
+                                    ("[{\"a\": [1,],},[],]", "[{\"a\": [1]},[]]")] {
+            let mut relaxed_parser = Parser::with_config(relaxed.chars(), config.clone());
+            let mut strict_parser = Parser::new(strict.chars());
+            loop {
+                let (r, s) = (relaxed_parser.next(), strict_parser.next());
+                assert_eq!(r, s);
+                let strict_stack = strict_parser.stack();
+                let expected: Vec<StackElement> =
+                    (0..strict_stack.len()).map(|i| strict_stack.get(i)).collect();
+                assert!(relaxed_parser.stack().is_equal_to(&expected));
+                if r.is_none() { break; }
+            }
+            assert_eq!(Json::from_str_relaxed(relaxed), Json::from_str(strict));
+        }
+
+        let mut parser = Parser::with_config("[[1,],2]".chars(), config.clone());
+        for _ in 0..4 { parser.next(); }
+        assert_eq!(parser.next(), Some(U64Value(2)));
+        assert!(parser.stack().is_equal_to(&[StackElement::Index(1)]));
+
+        assert_eq!(Json::from_str_relaxed("[1,,]"), Err(SyntaxError(InvalidSyntax, 1, 4)));
+        assert_eq!(Json::from_str_relaxed("[,]"), Err(SyntaxError(InvalidSyntax, 1, 2)));
+        assert_eq!(Json::from_str_relaxed("{,}"), Err(SyntaxError(KeyMustBeAString, 1, 2)));
+    }
+
+    #[test]
+    fn test_decoder_error_eq() {
+        use std::io;
+        use super::DecoderError::*;
+
+        let io_error = || ParseError(IoError(io::Error::new(io::ErrorKind::Other, "broken")));
+        assert!(io_error() != io_error());
+        assert!(io_error() != ParseError(SyntaxError(InvalidSyntax, 1, 1)));
+
+        assert_eq!(ParseError(SyntaxError(InvalidSyntax, 1, 1)),
+                   ParseError(SyntaxError(InvalidSyntax, 1, 1)));
+        assert!(ParseError(SyntaxError(InvalidSyntax, 1, 1)) !=
+                ParseError(SyntaxError(InvalidSyntax, 1, 2)));
+        assert_eq!(ExpectedError("Number".to_string(), "true".to_string()),
+                   ExpectedError("Number".to_string(), "true".to_string()));
+        assert!(ExpectedError("Number".to_string(), "true".to_string()) !=
+                ExpectedError("String".to_string(), "true".to_string()));
+        assert!(MissingFieldError("a".to_string()) != DuplicateKeyError("a".to_string()));
+        assert!(UnexpectedVariantError("C".to_string(), vec!["A".to_string()]) !=
+                UnexpectedVariantError("C".to_string(), vec![]));
+        assert!(UnknownVariantError("C".to_string()) !=
+                UnexpectedVariantError("C".to_string(), vec![]));
+        assert_eq!(EOF, EOF);
+        assert!(EOF != ApplicationError(string::String::new()));
+        assert_eq!(super::decode::<u8>("[]"), Err(ExpectedError("Number".to_string(),
+                                                                "[]".to_string())));
+    }
+
+    #[test]
+    fn test_from_reader_lossy() {
+        let bytes: &[u8] = b"{\"name\": \"caf\xc3\", \"n\": [1, 2]}";
+        assert_eq!(Json::from_reader(&mut &bytes[..]), Err(SyntaxError(NotUtf8, 0, 0)));
+        assert_eq!(Json::from_reader_lossy(&mut &bytes[..]),
+                   Ok(mk_object(&[("n".to_string(), Array(vec![U64(1), U64(2)])),
+                                  ("name".to_string(), String("caf\u{FFFD}".to_string()))])));
+        assert_eq!(Json::from_reader_lossy(&mut &b"[1,"[..]),
+                   Err(SyntaxError(EOFWhileParsingValue, 1, 4)));
+    }
+
+    #[test]
+    fn test_parser_error_position() {
+        let err = Json::from_str("{\n  \"a\": [1,\n    2.x]}").unwrap_err();
+        assert_eq!(err, SyntaxError(InvalidNumber, 3, 8));
+        assert_eq!(format!("{:?}", err), r#"SyntaxError("invalid number", 3, 8)"#);
+        assert_eq!(err.to_string(), "invalid number at line 3 column 8");
+        assert_eq!((err.line(), err.column()), (Some(3), Some(8)));
+
+        let err = IoError(::std::io::Error::new(::std::io::ErrorKind::Other, "broken"));
+        assert_eq!(err.to_string(), "broken");
+        assert_eq!((err.line(), err.column()), (None, None));
+    }
+
+    #[test]
+    fn test_report_offsets() {
+        use super::{ParserConfig, ByteParser, SyntaxErrorAt};
+
+        let config = ParserConfig { report_offsets: true, ..ParserConfig::default() };
+        let src = "{\"\u{e9}\": 1,\n \"\u{fc}\u{fc}\": x}";
+        let err = Json::from_str_with(src, &config).unwrap_err();
+        assert_eq!(err, SyntaxErrorAt(InvalidSyntax, 2, 9, 19));
+        assert!(src[err.offset().unwrap()..].starts_with("x}"));
+        assert_eq!(err.to_string(), "invalid syntax at line 2 column 9");
+        assert_eq!(Json::from_str(src).unwrap_err().offset(), None);
+
+        assert_eq!(Json::from_str_with("[\"\u{2603}\"", &config),
+                   Err(SyntaxErrorAt(EOFWhileParsingArray, 1, 5, 6)));
+        let events: Vec<JsonEvent> =
+            ByteParser::with_config("[\"\u{2603}\", tru]".bytes(), config).collect();
+        assert_eq!(events.last(), Some(&Error(SyntaxErrorAt(InvalidSyntax, 1, 10, 11))));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        use super::{ParserConfig, RecursionLimitExceeded};
+
+        let config = ParserConfig { max_depth: Some(2), ..ParserConfig::default() };
+        for src in &["1", "[]", "[[], {}]", "{\"a\": [1, 2]}", "[{}, [3]]"] {
+            assert!(Json::from_str_with(src, &config).is_ok());
+        }
+        assert_eq!(Json::from_str_with("[[[]]]", &config),
+                   Err(SyntaxError(RecursionLimitExceeded, 1, 3)));
+        assert_eq!(Json::from_str_with("{\"a\": {\"b\": {}}}", &config),
+                   Err(SyntaxError(RecursionLimitExceeded, 1, 13)));
+        assert_eq!(Json::from_str_with("[1, {\"a\": [2]}]", &config),
+                   Err(SyntaxError(RecursionLimitExceeded, 1, 11)));
+
+        let events: Vec<JsonEvent> =
+            Parser::with_config("[[[1]]]".chars(), config.clone()).collect();
+        assert_eq!(events, vec![ArrayStart, ArrayStart,
+                                Error(SyntaxError(RecursionLimitExceeded, 1, 3))]);
+
+        let deep = format!("{}{}", "[".repeat(100000), "]".repeat(100000));
+        let config = ParserConfig { max_depth: Some(128), ..ParserConfig::default() };
+        assert_eq!(Json::from_str_with(&deep, &config),
+                   Err(SyntaxError(RecursionLimitExceeded, 1, 129)));
+        assert_eq!(ParserConfig::default().max_depth, None);
+    }
+
+    #[test]
+    fn test_from_str_verbose() {
+        use super::{ParserConfig, Warning};
+
+        let config = ParserConfig {
+            allow_trailing_commas: true,
+            allow_comments: true,
+            allow_nonfinite: true,
+            ..ParserConfig::default()
+        };
+        let (json, warnings) = Json::from_str_verbose("{\"a\": [1, 2,], // two\n \"b\": -Infinity,}",
+                                                      &config).unwrap();
+        assert_eq!(json, Json::from_str_with("{\"a\": [1, 2], \"b\": -Infinity}", &config).unwrap());
+        assert_eq!(warnings, vec![Warning::TrailingComma(1, 12),
+                                  Warning::Comment(1, 16),
+                                  Warning::NonFiniteNumber(2, 8),
+                                  Warning::TrailingComma(2, 17)]);
+        assert_eq!(warnings[0].to_string(), "trailing comma at line 1 column 12");
+
+        let (_, warnings) = Json::from_str_verbose("[1, 2]", &config).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(Json::from_str_verbose("[1,]", &ParserConfig::default()),
+                   Err(SyntaxError(InvalidSyntax, 1, 4)));
+
+        // parsers only collect warnings when asked to
+        let mut parser = Parser::with_config("[NaN,]".chars(), config.clone());
+        assert_eq!(parser.by_ref().count(), 3);
+        assert_eq!(parser.warnings(), &[]);
+
+        let config = ParserConfig { collect_warnings: true, ..config };
+        let mut parser = Parser::with_config("[NaN]".chars(), config.clone());
+        assert_eq!(parser.warnings(), &[]);
+        parser.next();
+        parser.next();
+        assert_eq!(parser.warnings(), &[Warning::NonFiniteNumber(1, 2)]);
+        parser.reset("[]".chars());
+        assert_eq!(parser.warnings(), &[]);
+
+        // nothing is recorded for a literal which fails to parse
+        for input in &["[Nab]", "[-Infinite]"] {
+            let mut parser = Parser::with_config(input.chars(), config.clone());
+            assert!(parser.any(|e| match e { Error(_) => true, _ => false }));
+            assert_eq!(parser.warnings(), &[]);
+        }
+    }
+
+    #[test]
+    fn test_allow_nonfinite() {
+        use std::f64;
+        use super::ParserConfig;
+        use Encoder as EncoderTrait;
+
+        let config = ParserConfig { allow_nonfinite: true, ..ParserConfig::default() };
+        let events: Vec<JsonEvent> =
+            Parser::with_config("[Infinity, -Infinity, -1.5]".chars(), config.clone()).collect();
+        assert_eq!(events, vec![ArrayStart, F64Value(f64::INFINITY),
+                                F64Value(f64::NEG_INFINITY), F64Value(-1.5), ArrayEnd]);
+        match Json::from_str_with("{\"a\": NaN}", &config).unwrap().find("a") {
+            Some(&F64(v)) => assert!(v.is_nan()),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert_eq!(Json::from_str_with("Nan", &config), Err(SyntaxError(InvalidSyntax, 1, 3)));
+        assert_eq!(Json::from_str_with("-Inf", &config), Err(SyntaxError(InvalidSyntax, 1, 5)));
+        assert_eq!(Json::from_str_with("-NaN", &config), Err(SyntaxError(InvalidNumber, 1, 2)));
+        assert_eq!(Json::from_str("Infinity"), Err(SyntaxError(InvalidSyntax, 1, 1)));
+
+        let values = vec![f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 0.5];
+        assert_eq!(super::encode(&values).unwrap(), "[null,null,null,0.5]");
+        let mut s = string::String::new();
+        {
+            let mut encoder = Encoder::new(&mut s);
+            encoder.set_allow_nonfinite(true);
+            values.encode(&mut encoder).unwrap();
+        }
+        assert_eq!(s, "[NaN,Infinity,-Infinity,0.5]");
+        let decoded = Json::from_str_with(&s, &config).unwrap();
+        assert_eq!(decoded.pointer("/1"), Some(&F64(f64::INFINITY)));
+        assert_eq!(decoded.pointer("/2"), Some(&F64(f64::NEG_INFINITY)));
+
+        let mut encoder = Encoder::new(&mut s);
+        encoder.set_allow_nonfinite(true);
+        assert!(encoder.emit_map(1, |e| e.emit_map_elt_key(0, |e| e.emit_f64(f64::NAN))).is_err());
+    }
+
+    #[test]
+    fn test_parser_peek() {
+        let mut parser = Parser::new("[1, {}]".chars());
+        assert_eq!(parser.peek(), Some(&ArrayStart));
+        assert_eq!(parser.peek(), Some(&ArrayStart));
+        assert_eq!(parser.next(), Some(ArrayStart));
+        assert_eq!(parser.peek(), Some(&U64Value(1)));
+        assert_eq!(parser.next(), Some(U64Value(1)));
+        assert_eq!(parser.next(), Some(ObjectStart));
+        assert_eq!(parser.peek(), Some(&ObjectEnd));
+        assert_eq!(parser.next(), Some(ObjectEnd));
+        assert_eq!(parser.next(), Some(ArrayEnd));
+        assert_eq!(parser.peek(), None);
+        assert_eq!(parser.next(), None);
+    }
+
+    #[test]
+    fn test_builder_build_one() {
+        let mut builder = super::Builder::new("{\"a\": 1} [2]\n3\"b\" ".chars());
+        assert_eq!(builder.build_one(), Ok(Some(mk_object(&[("a".to_string(), U64(1))]))));
+        assert_eq!(builder.build_one(), Ok(Some(Array(vec![U64(2)]))));
+        assert_eq!(builder.build_one(), Ok(Some(U64(3))));
+        assert_eq!(builder.build_one(), Ok(Some(String("b".to_string()))));
+        assert_eq!(builder.build_one(), Ok(None));
+        assert_eq!(builder.build_one(), Ok(None));
+
+        let mut builder = super::Builder::new("[1] ]".chars());
+        assert_eq!(builder.build_one(), Ok(Some(Array(vec![U64(1)]))));
+        assert_eq!(builder.build_one(), Err(SyntaxError(InvalidSyntax, 1, 5)));
+
+        let mut builder = super::Builder::new("".chars());
+        assert_eq!(builder.build_one(), Ok(None));
+    }
+
+    #[test]
+    fn test_parser_reset() {
+        let docs = [r#"{"a": [1, "x\ny"], "b": null}"#, "[true]", "{\"a\" 1}", "\n  2.5"];
+        let mut parser = Parser::new(r#"{"z": [{"#.chars());
+        assert_eq!(parser.next(), Some(ObjectStart));
+        assert_eq!(parser.next(), Some(ArrayStart));
+        for doc in docs.iter() {
+            parser.reset(doc.chars());
+            let reused: Vec<JsonEvent> = parser.by_ref().collect();
+            let fresh: Vec<JsonEvent> = Parser::new(doc.chars()).collect();
+            assert_eq!(reused, fresh);
+        }
+    }
+
+    #[test]
+    fn test_byte_parser() {
+        use super::ByteParser;
+
+        let docs = ["{\"a\": [1, 2.5e3, -4], \"b\": \"h\u{e9}\u{1F600}\\u00e9\"}",
+                    "[true,\n false, null]", "\n  \"caf\u{e9}\"", "{\"a\" 1}", "[1,\n 2,,]", ""];
+        for doc in docs.iter() {
+            let from_bytes: Vec<JsonEvent> = ByteParser::new(doc.bytes()).collect();
+            let from_chars: Vec<JsonEvent> = Parser::new(doc.chars()).collect();
+            assert_eq!(from_bytes, from_chars);
+        }
+
+        let events: Vec<JsonEvent> = ByteParser::new(b"[1, \"a\xffb\"]".iter().cloned())
+            .collect();
+        assert_eq!(events, vec![ArrayStart, U64Value(1),
+                                Error(SyntaxError(NotUtf8, 1, 7))]);
+        let events: Vec<JsonEvent> = ByteParser::new(b"12\xe9".iter().cloned()).collect();
+        assert_eq!(events, vec![U64Value(12), Error(SyntaxError(NotUtf8, 1, 3))]);
+        // a truncated multibyte sequence
+        let events: Vec<JsonEvent> = ByteParser::new(b"\"\xf0\x9f\x98".iter().cloned())
+            .collect();
+        assert_eq!(events, vec![Error(SyntaxError(NotUtf8, 1, 2))]);
+    }
+
+    #[test]
+    fn test_reader_parser() {
+        use std::{cmp, io};
+        use super::ReaderParser;
+
+        // Returns at most `chunk` bytes per read, then fails after `limit`.
+        struct Chunked<'a> { data: &'a [u8], chunk: usize, limit: usize }
+        impl<'a> io::Read for Chunked<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.limit == 0 {
+                    return Err(io::Error::new(io::ErrorKind::Other, "broken"));
+                }
+                let n = cmp::min(cmp::min(self.chunk, self.limit),
+                                 cmp::min(buf.len(), self.data.len()));
+                buf[..n].copy_from_slice(&self.data[..n]);
+                self.data = &self.data[n..];
+                self.limit -= n;
+                Ok(n)
+            }
+        }
+
+        let docs = ["[{\"a\": 1.5, \"b\": \"caf\u{e9} \u{1F600}\"},\n {\"c\": [true, null]}]",
+                    "[1,\n 2,,]", "{\"a\" 1}"];
+        for doc in docs.iter() {
+            let expected: Vec<JsonEvent> = Parser::new(doc.chars()).collect();
+            for chunk in 1..4 {
+                let reader = Chunked { data: doc.as_bytes(), chunk: chunk, limit: !0 };
+                let events: Vec<JsonEvent> = ReaderParser::new(reader).collect();
+                assert_eq!(events, expected);
+            }
+        }
+
+        let reader = Chunked { data: b"[1, 23, 4]", chunk: 2, limit: 6 };
+        let mut parser = ReaderParser::new(reader);
+        assert_eq!(parser.next(), Some(ArrayStart));
+        assert_eq!(parser.next(), Some(U64Value(1)));
+        // "23" may have been the start of a longer number
+        match parser.next() {
+            Some(Error(IoError(ref e))) => assert_eq!(e.to_string(), "broken"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(parser.next(), None);
+    }
+
+    #[test]
+    fn test_transform_array() {
+        use std::io;
+        use super::{transform_array, ParserError};
+
+        let input = r#"[{"id": 1, "tags": ["a"]}, {"id": 2, "tags": []}, 3, "x"]"#;
+        let mut output = Vec::new();
+        let mut seen = 0;
+        transform_array(input.as_bytes(), &mut output, |json| {
+            seen += 1;
+            match json {
+                Object(mut obj) => {
+                    obj.remove("tags");
+                    obj.insert("seen".to_string(), U64(seen));
+                    Object(obj)
+                }
+                other => Array(vec![other]),
+            }
+        }).unwrap();
+        assert_eq!(string::String::from_utf8(output).unwrap(),
+                   r#"[{"id":1,"seen":1},{"id":2,"seen":2},[3],["x"]]"#);
+
+        let mut output = Vec::new();
+        transform_array(&b" [ ] "[..], &mut output, |json| json).unwrap();
+        assert_eq!(output, b"[]");
+
+        let transform = |input: &[u8]| transform_array(input, Vec::new(), |json| json);
+        assert_eq!(transform(b"{}"), Err(SyntaxError(InvalidSyntax, 1, 2)));
+        assert_eq!(transform(b"[1, 2"), Err(SyntaxError(EOFWhileParsingArray, 1, 6)));
+        assert_eq!(transform(b"[1] 2"), Err(SyntaxError(TrailingCharacters, 1, 5)));
+        assert_eq!(transform(b"[1, \"\xff\"]"), Err(SyntaxError(NotUtf8, 1, 6)));
+
+        let err = EncoderError::BadNumber("x".to_string());
+        assert_eq!(ParserError::from(err.clone()), EncodeError(err));
+        let err = EncoderError::IoError {
+            source: io::Error::new(io::ErrorKind::Other, "oops"),
+            bytes_written: 3,
+        };
+        match ParserError::from(err) {
+            IoError(e) => assert_eq!(e.to_string(), "oops"),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
     #[test]
     fn test_stack() {
         let mut stack = Stack::new();
@@ -3872,9 +8404,36 @@ mod tests {
         assert!(stack.get(1) == Key("foo"));
     }
 
+    #[test]
+    fn test_stack_long_keys() {
+        let long: string::String = (0..70000).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let mut stack = Stack::new();
+        stack.push_key(long.clone());
+        stack.push_key("x".repeat(40000));
+        stack.push_key("after".to_string());
+        assert!(stack.get(0) == Key(&long));
+        assert!(stack.get(1) == Key(&"x".repeat(40000)));
+        assert!(stack.top() == Some(Key("after")));
+        stack.pop();
+        stack.pop();
+        assert!(stack.top() == Some(Key(&long)));
+
+        // through the parser
+        let json = format!("{{\"{}\": {{\"{}\": [1]}}}}", long, long);
+        let mut parser = Parser::new(json.chars());
+        let mut saw_index = false;
+        while let Some(evt) = parser.next() {
+            if evt == U64Value(1) {
+                assert!(parser.stack().is_equal_to(&[Key(&long), Key(&long), Index(0)]));
+                saw_index = true;
+            }
+        }
+        assert!(saw_index);
+    }
+
     #[test]
     fn test_to_json() {
-        use std::collections::{HashMap,BTreeMap};
+        use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
         use super::ToJson;
 
         let array2 = Array(vec!(I64(1), I64(2)));
@@ -3905,6 +8464,9 @@ mod tests {
         assert_eq!(f64::NAN.to_json(), Null);
         assert_eq!(true.to_json(), Boolean(true));
         assert_eq!(false.to_json(), Boolean(false));
+        assert_eq!('a'.to_json(), String("a".to_string()));
+        assert_eq!('\x01'.to_json(), String("\x01".to_string()));
+        assert_eq!('\x01'.to_json().to_string(), "\"\\u0001\"");
         assert_eq!("abc".to_json(), String("abc".to_string()));
         assert_eq!("abc".to_string().to_json(), String("abc".to_string()));
         assert_eq!((1, 2).to_json(), array2);
@@ -3921,9 +8483,130 @@ mod tests {
         hash_map.insert("a".to_string(), 1 as u32);
         hash_map.insert("b".to_string(), 2);
         assert_eq!(hash_map.to_json(), object);
+        let btree_set: BTreeSet<_> = vec![2, 1].into_iter().collect();
+        assert_eq!(btree_set.to_json(), array2);
+        let hash_set: HashSet<_> = vec![3].into_iter().collect();
+        assert_eq!(hash_set.to_json(), Array(vec![I64(3)]));
+        assert_eq!(HashSet::<i32>::new().to_json(), Array(vec![]));
         assert_eq!(Some(15).to_json(), I64(15));
         assert_eq!(Some(15 as u32).to_json(), U64(15));
         assert_eq!(None::<isize>.to_json(), Null);
+        let results: [Result<Result<u32, bool>, string::String>; 3] =
+            [Ok(Ok(1)), Ok(Err(true)), Err("e".to_string())];
+        for result in results.iter() {
+            assert_eq!(result.to_json(), Json::from_str(&super::encode(result).unwrap()).unwrap());
+            let decoded: Result<Result<u32, bool>, string::String> =
+                super::decode(&result.to_json().to_string()).unwrap();
+            assert_eq!(&decoded, result);
+        }
+        assert_eq!(Ok::<u32, ()>(1).to_json().to_string(), r#"{"fields":[1],"variant":"Ok"}"#);
+
+        fn generic<T: ToJson>(t: T) -> Json { t.to_json() }
+        assert_eq!((&5i32).to_json(), I64(5));
+        assert_eq!((&"x").to_json(), String("x".to_string()));
+        assert_eq!(generic(&5i32), I64(5));
+        assert_eq!(generic(&mut vec![1, 2]), array2);
+        assert_eq!(generic(vec![&"x"]), Array(vec![String("x".to_string())]));
+
+        use std::rc::Rc;
+        use std::sync::Arc;
+        assert_eq!(Box::new(5i32).to_json(), I64(5));
+        assert_eq!(Rc::new(vec![1, 2]).to_json(), array2);
+        assert_eq!(Arc::new(Some(15)).to_json(), I64(15));
+        let unsized_box: Box<str> = "x".to_string().into_boxed_str();
+        assert_eq!(unsized_box.to_json(), String("x".to_string()));
+        assert_eq!(generic(Rc::new(Box::new(true))), Boolean(true));
+    }
+
+    #[test]
+    fn test_encode_to_vec() {
+        let value = vec![Some("a\u{e9}\n".to_string()), None];
+        assert_eq!(super::encode_to_vec(&value).unwrap(),
+                   super::encode(&value).unwrap().into_bytes());
+        assert_eq!(super::encode_to_vec(&Vec::<u8>::new()).unwrap(), b"[]");
+    }
+
+    #[test]
+    fn test_to_writer_io_error() {
+        use std::{cmp, io};
+
+        // Accepts up to `limit` bytes, then fails.
+        struct FailAfter { buf: Vec<u8>, limit: usize }
+
+        impl io::Write for FailAfter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = cmp::min(self.limit - self.buf.len(), buf.len());
+                if n == 0 {
+                    return Err(io::Error::new(io::ErrorKind::Other, "full"));
+                }
+                self.buf.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        let value = vec!["abc".to_string(), "def".to_string()];
+
+        let mut writer = FailAfter { buf: Vec::new(), limit: 5 };
+        match super::to_writer(&mut writer, &value) {
+            Err(EncoderError::IoError { ref source, bytes_written: 5 }) => {
+                assert_eq!(source.to_string(), "full")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(writer.buf, b"[\"abc");
+
+        let mut writer = FailAfter { buf: Vec::new(), limit: 100 };
+        assert_eq!(super::to_writer(&mut writer, &value), Ok(()));
+        assert_eq!(writer.buf, b"[\"abc\",\"def\"]");
+
+        let mut writer = FailAfter { buf: Vec::new(), limit: 100 };
+        assert_eq!(super::to_writer_pretty(&mut writer, &value), Ok(()));
+        assert_eq!(writer.buf, super::as_pretty_json(&value).to_string().as_bytes());
+
+        let mut writer = FailAfter { buf: Vec::new(), limit: 8 };
+        match super::to_writer_pretty(&mut writer, &value) {
+            Err(EncoderError::IoError { ref source, bytes_written: 8 }) => {
+                assert_eq!(source.to_string(), "full")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(writer.buf, b"[\n  \"abc");
+    }
+
+    #[test]
+    fn test_io_encoder_finish() {
+        use std::io::{self, Write};
+        use super::IoEncoder;
+
+        // Holds back written bytes until flushed.
+        struct Buffered { pending: Vec<u8>, out: Vec<u8> }
+
+        impl Write for Buffered {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.pending.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.out.extend_from_slice(&self.pending);
+                self.pending.clear();
+                Ok(())
+            }
+        }
+
+        let mut writer = Buffered { pending: Vec::new(), out: Vec::new() };
+        {
+            let mut encoder = IoEncoder::new(&mut writer);
+            encoder.encode(&vec![1, 2]).unwrap();
+            encoder.encode(&"a").unwrap();
+            encoder.finish().unwrap();
+        }
+        assert!(writer.pending.is_empty());
+        assert_eq!(writer.out, b"[1,2]\"a\"");
+
+        let mut encoder = IoEncoder::new(Vec::new());
+        encoder.encode(&true).unwrap();
+        assert_eq!(encoder.into_writer(), b"true");
     }
 
     #[test]
@@ -3942,6 +8625,130 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_encode_decode_path() {
+        use std::path::{Path, PathBuf};
+
+        let path = Path::new("dir/caf\u{e9} \"1\".txt");
+        let encoded = super::encode(&path).unwrap();
+        assert_eq!(encoded, r#""dir/café \"1\".txt""#);
+        assert_eq!(super::encode(&path.to_path_buf()).unwrap(), encoded);
+        assert_eq!(super::decode::<PathBuf>(&encoded).unwrap(), path);
+        assert!(super::decode::<PathBuf>("{}").is_err());
+        #[cfg(any(unix, windows))]
+        assert_eq!(super::decode::<PathBuf>("[100, 105, 114]").unwrap(), Path::new("dir"));
+
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+
+            let path = Path::new(OsStr::from_bytes(b"a\xffb"));
+            assert_eq!(super::decode::<PathBuf>("[97, 255, 98]").unwrap(), path);
+            assert_eq!(super::encode(&path),
+                       Err(EncoderError::ApplicationError("path is not valid UTF-8".to_string())));
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_net_addrs() {
+        use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+        let v4 = Ipv4Addr::new(127, 0, 0, 1);
+        let v6 = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1);
+        assert_eq!(super::encode(&v4).unwrap(), "\"127.0.0.1\"");
+        assert_eq!(super::encode(&v6).unwrap(), "\"::1\"");
+        assert_eq!(super::decode::<Ipv4Addr>("\"127.0.0.1\"").unwrap(), v4);
+        assert_eq!(super::decode::<Ipv6Addr>("\"::1\"").unwrap(), v6);
+        assert_eq!(super::decode::<IpAddr>("\"::1\"").unwrap(), IpAddr::V6(v6));
+
+        let addrs = [SocketAddr::V4(SocketAddrV4::new(v4, 8080)),
+                     SocketAddr::V6(SocketAddrV6::new(v6, 443, 0, 0))];
+        let encoded: Vec<string::String> =
+            addrs.iter().map(|a| super::encode(a).unwrap()).collect();
+        assert_eq!(encoded, ["\"127.0.0.1:8080\"", "\"[::1]:443\""]);
+        for (addr, s) in addrs.iter().zip(encoded.iter()) {
+            assert_eq!(&super::decode::<SocketAddr>(s).unwrap(), addr);
+        }
+        assert_eq!(super::decode::<SocketAddrV4>("\"127.0.0.1:8080\"").unwrap(),
+                   SocketAddrV4::new(v4, 8080));
+
+        assert_eq!(super::decode::<Ipv4Addr>("\"::1\""),
+                   Err(ApplicationError("invalid IPv4 address".to_string())));
+        assert_eq!(super::decode::<SocketAddr>("\"127.0.0.1\""),
+                   Err(ApplicationError("invalid socket address".to_string())));
+        assert!(super::decode::<IpAddr>("[127, 0, 0, 1]").is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_duration() {
+        use std::time::Duration;
+
+        let duration = Duration::new(5, 250);
+        let encoded = super::encode(&duration).unwrap();
+        assert_eq!(encoded, "{\"secs\":5,\"nanos\":250}");
+        assert_eq!(super::decode::<Duration>(&encoded).unwrap(), duration);
+        let max = Duration::new(u64::max_value(), 999_999_999);
+        assert_eq!(super::decode::<Duration>(&super::encode(&max).unwrap()).unwrap(), max);
+
+        assert_eq!(super::decode::<Duration>("{\"secs\":0,\"nanos\":1000000000}"),
+                   Err(ApplicationError("nanos out of range".to_string())));
+        assert!(super::decode::<Duration>("{\"secs\":-1,\"nanos\":0}").is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_system_time() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let now = SystemTime::now();
+        let decoded: SystemTime = super::decode(&super::encode(&now).unwrap()).unwrap();
+        assert_eq!(decoded, now);
+
+        let time = UNIX_EPOCH + Duration::new(5, 250);
+        assert_eq!(super::encode(&time).unwrap(),
+                   "{\"secs_since_epoch\":5,\"nanos_since_epoch\":250}");
+
+        let time = UNIX_EPOCH - Duration::new(5, 250);
+        let encoded = super::encode(&time).unwrap();
+        assert_eq!(encoded, "{\"secs_since_epoch\":-6,\"nanos_since_epoch\":999999750}");
+        assert_eq!(super::decode::<SystemTime>(&encoded).unwrap(), time);
+
+        assert!(super::decode::<SystemTime>(
+            "{\"secs_since_epoch\":0,\"nanos_since_epoch\":1000000000}").is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_binary_heap() {
+        use std::collections::BinaryHeap;
+
+        let heap: BinaryHeap<u32> = vec![3, 1, 4, 1, 5].into_iter().collect();
+        let decoded: BinaryHeap<u32> = super::decode(&super::encode(&heap).unwrap()).unwrap();
+        assert_eq!(decoded.into_sorted_vec(), vec![1, 1, 3, 4, 5]);
+
+        let mut decoded: BinaryHeap<u32> = super::decode("[2, 9, 4]").unwrap();
+        assert_eq!(decoded.pop(), Some(9));
+        assert_eq!(super::encode(&BinaryHeap::<u32>::new()).unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_encode_decode_linked_list_and_vec_deque() {
+        use std::collections::{LinkedList, VecDeque};
+
+        let list: LinkedList<u32> = vec![1, 2, 3].into_iter().collect();
+        let s = super::encode(&list).unwrap();
+        assert_eq!(s, "[1,2,3]");
+        let decoded: LinkedList<u32> = super::decode(&s).unwrap();
+        assert_eq!(decoded, list);
+
+        let mut deque: VecDeque<string::String> = VecDeque::new();
+        deque.push_back("b".to_string());
+        deque.push_front("a".to_string());
+        let s = super::encode(&deque).unwrap();
+        assert_eq!(s, "[\"a\",\"b\"]");
+        let decoded: VecDeque<string::String> = super::decode(&s).unwrap();
+        assert_eq!(decoded, deque);
+    }
+
     #[test]
     fn test_encode_decode_phantom_data() {
         use std::marker::PhantomData;
@@ -25,7 +25,7 @@
 //!
 //! ```toml
 //! [dependencies]
-//! rustc-serialize = "0.3"
+//! rustc-serialize = "0.4"
 //! ```
 //!
 //! and this to your crate root:
@@ -51,7 +51,7 @@
 #[cfg(test)] extern crate rand;
 
 pub use self::serialize::{Decoder, Encoder, Decodable, Encodable,
-                          DecoderHelpers, EncoderHelpers};
+                          DecoderHelpers, EncoderHelpers, StrOrSeq};
 
 
 // Limit collections from allocating more than
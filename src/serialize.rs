@@ -22,12 +22,15 @@ Core encoding and decoding interfaces.
 */
 
 use std::cell::{Cell, RefCell};
-use std::ffi::OsString;
+use std::net;
 use std::path;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 use std::marker::PhantomData;
+use std::ops::Bound;
+use std::time::Duration;
 use std::borrow::Cow;
+use std::convert::TryFrom;
 
 use cap_capacity;
 
@@ -57,6 +60,14 @@ pub trait Encoder {
     /// Emit a usize value.
     fn emit_usize(&mut self, v: usize) -> Result<(), Self::Error>;
 
+    /// Emit a u128 value.
+    ///
+    /// The default implementation emits the decimal digits of the value with
+    /// `emit_str`, which the default `read_u128` reads back.
+    fn emit_u128(&mut self, v: u128) -> Result<(), Self::Error> {
+        self.emit_str(&v.to_string())
+    }
+
     /// Emit a u64 value.
     fn emit_u64(&mut self, v: u64) -> Result<(), Self::Error>;
 
@@ -72,6 +83,14 @@ pub trait Encoder {
     /// Emit a isize value.
     fn emit_isize(&mut self, v: isize) -> Result<(), Self::Error>;
 
+    /// Emit a i128 value.
+    ///
+    /// The default implementation emits the decimal digits of the value with
+    /// `emit_str`, which the default `read_i128` reads back.
+    fn emit_i128(&mut self, v: i128) -> Result<(), Self::Error> {
+        self.emit_str(&v.to_string())
+    }
+
     /// Emit a i64 value.
     fn emit_i64(&mut self, v: i64) -> Result<(), Self::Error>;
 
@@ -168,6 +187,29 @@ pub trait Encoder {
                             f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error>;
 
+    /// Emit a enumeration variant value with no or unnamed data, using the
+    /// given names for the fields holding the variant name and its data in
+    /// formats which represent them as separate fields.
+    ///
+    /// This behaves like `emit_enum_variant` otherwise, and allows an
+    /// `Encodable` implementation to choose these names for its own type.
+    /// Encoders which do not name these fields need not implement this
+    /// method; by default it ignores the names and calls `emit_enum_variant`.
+    ///
+    /// * `_variant_key` is the name of the field holding the variant name.
+    /// * `_content_key` is the name of the field holding the data items.
+    fn emit_enum_variant_tagged<F>(&mut self,
+                                   _variant_key: &str,
+                                   _content_key: &str,
+                                   v_name: &str,
+                                   v_id: usize,
+                                   len: usize,
+                                   f: F) -> Result<(), Self::Error>
+        where F: FnOnce(&mut Self) -> Result<(), Self::Error>
+    {
+        self.emit_enum_variant(v_name, v_id, len, f)
+    }
+
     /// Emit an unnamed data item for an enumeration variant.
     ///
     /// This should only be called from a function passed to
@@ -486,6 +528,34 @@ pub trait Encoder {
     /// emitted followed immediately by the value.
     fn emit_map_elt_val<F>(&mut self, idx: usize, f: F) -> Result<(), Self::Error>
         where F: FnOnce(&mut Self) -> Result<(), Self::Error>;
+
+    /// Returns whether maps which have no inherent order, such as `HashMap`,
+    /// should emit their entries sorted by key rather than in iteration
+    /// order, so that they are the same on every run of the program. Keys
+    /// which encode as strings are compared as strings, and other keys by
+    /// their JSON encoding.
+    ///
+    /// This defaults to `false`.
+    fn sort_keys(&self) -> bool { false }
+
+    // Failure
+    /// Record an encoding error.
+    ///
+    /// This allows `Encodable` implementations to report a value which cannot
+    /// be encoded, such as a poisoned lock, using an `Encoder`
+    /// implementation's error type. Encoders which have no way to represent
+    /// such an error return `None`, which is the default.
+    fn error(&mut self, _err: &str) -> Option<Self::Error> { None }
+}
+
+/// A value read by `Decoder::read_str_or_seq`, which is either a string or a
+/// sequence.
+#[derive(Clone, PartialEq, Debug)]
+pub enum StrOrSeq<T> {
+    /// A string value
+    Str(String),
+    /// A sequence of values
+    Seq(Vec<T>),
 }
 
 /// Trait for reading in an encoding for deserialization.
@@ -513,6 +583,15 @@ pub trait Decoder {
     /// Read a usize value.
     fn read_usize(&mut self) -> Result<usize, Self::Error>;
 
+    /// Read a u128 value.
+    ///
+    /// The default implementation reads a string of decimal digits with
+    /// `read_str`, as written by the default `emit_u128`.
+    fn read_u128(&mut self) -> Result<u128, Self::Error> {
+        let s = try!(self.read_str());
+        s.parse().map_err(|_| self.error(&format!("invalid u128 value: {}", s)))
+    }
+
     /// Read a u64 value.
     fn read_u64(&mut self) -> Result<u64, Self::Error>;
 
@@ -528,6 +607,15 @@ pub trait Decoder {
     /// Read a isize value.
     fn read_isize(&mut self) -> Result<isize, Self::Error>;
 
+    /// Read a i128 value.
+    ///
+    /// The default implementation reads a string of decimal digits with
+    /// `read_str`, as written by the default `emit_i128`.
+    fn read_i128(&mut self) -> Result<i128, Self::Error> {
+        let s = try!(self.read_str());
+        s.parse().map_err(|_| self.error(&format!("invalid i128 value: {}", s)))
+    }
+
     /// Read a i64 value.
     fn read_i64(&mut self) -> Result<i64, Self::Error>;
 
@@ -555,6 +643,16 @@ pub trait Decoder {
     /// Read a string value.
     fn read_str(&mut self) -> Result<String, Self::Error>;
 
+    /// Read a value which is either a string or a sequence of `T`. This lets
+    /// `PathBuf` read the sequences of path units earlier versions of this
+    /// crate encoded paths as.
+    ///
+    /// Formats which can tell the two apart should read whichever is next.
+    /// The default implementation reads a string.
+    fn read_str_or_seq<T: Decodable>(&mut self) -> Result<StrOrSeq<T>, Self::Error> {
+        self.read_str().map(StrOrSeq::Str)
+    }
+
     // Compound types:
     /// Read an enumeration value.
     ///
@@ -727,6 +825,20 @@ pub trait Decoder {
     fn read_option<T, F>(&mut self, f: F) -> Result<T, Self::Error>
         where F: FnMut(&mut Self, bool) -> Result<T, Self::Error>;
 
+    /// Read an optional value which itself holds an optional value, as for
+    /// `Option<Option<T>>`.
+    ///
+    /// Formats which can tell an absent value apart from a null one should
+    /// call `f` with `false` only for an absent value, and with `true` for a
+    /// null, which the inner `read_option` then reads as `None`.
+    ///
+    /// The default implementation is `read_option`.
+    fn read_nested_option<T, F>(&mut self, f: F) -> Result<T, Self::Error>
+        where F: FnMut(&mut Self, bool) -> Result<T, Self::Error>
+    {
+        self.read_option(f)
+    }
+
     /// Read a sequence of values.
     ///
     /// This should be used for both array-like ordered sequences and set-like
@@ -737,6 +849,16 @@ pub trait Decoder {
     fn read_seq<T, F>(&mut self, f: F) -> Result<T, Self::Error>
         where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error>;
 
+    /// Read a sequence of bytes, as for `Vec<u8>`, like `read_seq`.
+    ///
+    /// Formats with a more compact representation of bytes may accept it
+    /// here as well. The default implementation is `read_seq`.
+    fn read_bytes<T, F>(&mut self, f: F) -> Result<T, Self::Error>
+        where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error>
+    {
+        self.read_seq(f)
+    }
+
     /// Read an element in the sequence.
     ///
     /// This should only be called from a function passed to `read_seq`.
@@ -902,6 +1024,16 @@ pub trait Encodable {
 pub trait Decodable: Sized {
     /// Deserialize a value using a `Decoder`.
     fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error>;
+
+    /// Returns whether this type is an `Option`, so that an `Option` of it
+    /// is read with `Decoder::read_nested_option`. Only the implementation
+    /// for `Option<T>` should return `true`.
+    fn is_option() -> bool { false }
+
+    /// Returns whether this type is `u8`, so that a `Vec` of it is read with
+    /// `Decoder::read_bytes`. Only the implementation for `u8` should return
+    /// `true`.
+    fn is_byte() -> bool { false }
 }
 
 impl Encodable for usize {
@@ -926,6 +1058,8 @@ impl Decodable for u8 {
     fn decode<D: Decoder>(d: &mut D) -> Result<u8, D::Error> {
         d.read_u8()
     }
+
+    fn is_byte() -> bool { true }
 }
 
 impl Encodable for u16 {
@@ -964,6 +1098,18 @@ impl Decodable for u64 {
     }
 }
 
+impl Encodable for u128 {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_u128(*self)
+    }
+}
+
+impl Decodable for u128 {
+    fn decode<D: Decoder>(d: &mut D) -> Result<u128, D::Error> {
+        d.read_u128()
+    }
+}
+
 impl Encodable for isize {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
         s.emit_isize(*self)
@@ -1024,6 +1170,18 @@ impl Decodable for i64 {
     }
 }
 
+impl Encodable for i128 {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_i128(*self)
+    }
+}
+
+impl Decodable for i128 {
+    fn decode<D: Decoder>(d: &mut D) -> Result<i128, D::Error> {
+        d.read_i128()
+    }
+}
+
 impl Encodable for str {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
         s.emit_str(self)
@@ -1181,13 +1339,18 @@ impl<T:Encodable> Encodable for Vec<T> {
 
 impl<T:Decodable> Decodable for Vec<T> {
     fn decode<D: Decoder>(d: &mut D) -> Result<Vec<T>, D::Error> {
-        d.read_seq(|d, len| {
+        let read = |d: &mut D, len| {
             let mut v = Vec::with_capacity(cap_capacity::<T>(len));
             for i in 0..len {
                 v.push(try!(d.read_seq_elt(i, |d| Decodable::decode(d))));
             }
             Ok(v)
-        })
+        };
+        if T::is_byte() {
+            d.read_bytes(read)
+        } else {
+            d.read_seq(read)
+        }
     }
 }
 
@@ -1204,14 +1367,21 @@ impl<T:Encodable> Encodable for Option<T> {
 
 impl<T:Decodable> Decodable for Option<T> {
     fn decode<D: Decoder>(d: &mut D) -> Result<Option<T>, D::Error> {
-        d.read_option(|d, b| {
+        let read = |d: &mut D, b| {
             if b {
                 Ok(Some(try!(Decodable::decode(d))))
             } else {
                 Ok(None)
             }
-        })
+        };
+        if T::is_option() {
+            d.read_nested_option(read)
+        } else {
+            d.read_option(read)
+        }
     }
+
+    fn is_option() -> bool { true }
 }
 
 impl<T:Encodable, E:Encodable> Encodable for Result<T, E> {
@@ -1261,6 +1431,64 @@ impl<T: Decodable, E: Decodable> Decodable for Result<T, E> {
     }
 }
 
+impl<T: Encodable> Encodable for Bound<T> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_enum("Bound", |s| {
+            match *self {
+                Bound::Included(ref v) => {
+                    s.emit_enum_variant("Included", 0, 1, |s| {
+                        s.emit_enum_variant_arg(0, |s| v.encode(s))
+                    })
+                }
+                Bound::Excluded(ref v) => {
+                    s.emit_enum_variant("Excluded", 1, 1, |s| {
+                        s.emit_enum_variant_arg(0, |s| v.encode(s))
+                    })
+                }
+                Bound::Unbounded => s.emit_enum_variant("Unbounded", 2, 0, |_| Ok(())),
+            }
+        })
+    }
+}
+
+impl<T: Decodable> Decodable for Bound<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Bound<T>, D::Error> {
+        d.read_enum("Bound", |d| {
+            d.read_enum_variant(&["Included", "Excluded", "Unbounded"], |d, idx| {
+                match idx {
+                    0 => d.read_enum_variant_arg(0, T::decode).map(Bound::Included),
+                    1 => d.read_enum_variant_arg(0, T::decode).map(Bound::Excluded),
+                    2 => Ok(Bound::Unbounded),
+                    _ => panic!("Internal error"),
+                }
+            })
+        })
+    }
+}
+
+impl Encodable for Duration {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_struct("Duration", 2, |s| {
+            try!(s.emit_struct_field("secs", 0, |s| self.as_secs().encode(s)));
+            try!(s.emit_struct_field("nanos", 1, |s| self.subsec_nanos().encode(s)));
+            Ok(())
+        })
+    }
+}
+
+impl Decodable for Duration {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Duration, D::Error> {
+        d.read_struct("Duration", 2, |d| {
+            let secs: u64 = try!(d.read_struct_field("secs", 0, Decodable::decode));
+            let nanos: u32 = try!(d.read_struct_field("nanos", 1, Decodable::decode));
+            if nanos >= 1_000_000_000 {
+                return Err(d.error("nanos out of range"));
+            }
+            Ok(Duration::new(secs, nanos))
+        })
+    }
+}
+
 impl<T> Encodable for PhantomData<T> {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
         s.emit_nil()
@@ -1319,57 +1547,47 @@ macro_rules! tuple {
 
 tuple! { T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, }
 
-macro_rules! array {
-    () => ();
-    ($len:expr, $($idx:expr,)*) => {
-        impl<T:Decodable> Decodable for [T; $len] {
-            fn decode<D: Decoder>(d: &mut D) -> Result<[T; $len], D::Error> {
-                d.read_seq(|d, len| {
-                    if len != $len {
-                        return Err(d.error("wrong array length"));
-                    }
-                    Ok([$(
-                        try!(d.read_seq_elt($len - $idx - 1,
-                                            |d| Decodable::decode(d)))
-                    ),*])
-                })
+impl<T: Decodable, const N: usize> Decodable for [T; N] {
+    fn decode<D: Decoder>(d: &mut D) -> Result<[T; N], D::Error> {
+        d.read_seq(|d, len| {
+            if len != N {
+                return Err(d.error("wrong array length"));
             }
-        }
-
-        impl<T:Encodable> Encodable for [T; $len] {
-            fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-                s.emit_seq($len, |s| {
-                    for i in 0..$len {
-                        try!(s.emit_seq_elt(i, |s| self[i].encode(s)));
-                    }
-                    Ok(())
-                })
+            let mut v = Vec::with_capacity(N);
+            for i in 0..N {
+                v.push(try!(d.read_seq_elt(i, |d| Decodable::decode(d))));
             }
-        }
-        array! { $($idx,)* }
+            match <[T; N]>::try_from(v) {
+                Ok(array) => Ok(array),
+                Err(_) => unreachable!(),
+            }
+        })
     }
 }
 
-array! {
-    32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16,
-    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+impl<T: Encodable, const N: usize> Encodable for [T; N] {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_seq(N, |s| {
+            for (i, e) in self.iter().enumerate() {
+                try!(s.emit_seq_elt(i, |s| e.encode(s)));
+            }
+            Ok(())
+        })
+    }
 }
 
+/// Paths are encoded as strings. A path which is not valid UTF-8 is reported
+/// with the encoder's `error`, or encoded with its invalid sequences replaced
+/// by U+FFFD when the encoder has no such error.
 impl Encodable for path::Path {
-    #[cfg(target_os = "redox")]
     fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
-        self.as_os_str().to_str().unwrap().encode(e)
-    }
-    #[cfg(unix)]
-    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
-        use std::os::unix::prelude::*;
-        self.as_os_str().as_bytes().encode(e)
-    }
-    #[cfg(windows)]
-    fn encode<S: Encoder>(&self, e: &mut S) -> Result<(), S::Error> {
-        use std::os::windows::prelude::*;
-        let v = self.as_os_str().encode_wide().collect::<Vec<_>>();
-        v.encode(e)
+        match self.to_str() {
+            Some(s) => e.emit_str(s),
+            None => match e.error("path is not valid UTF-8") {
+                Some(err) => Err(err),
+                None => e.emit_str(&self.to_string_lossy()),
+            },
+        }
     }
 }
 
@@ -1379,33 +1597,60 @@ impl Encodable for path::PathBuf {
     }
 }
 
+/// Paths are decoded from strings, or from the sequences of bytes (on Unix)
+/// or UTF-16 code units (on Windows) which earlier versions encoded them as.
 impl Decodable for path::PathBuf {
-    #[cfg(target_os = "redox")]
-    fn decode<D: Decoder>(d: &mut D) -> Result<path::PathBuf, D::Error> {
-        let string: String = try!(Decodable::decode(d));
-        let s: OsString = OsString::from(string);
-        let mut p = path::PathBuf::new();
-        p.push(s);
-        Ok(p)
-    }
     #[cfg(unix)]
     fn decode<D: Decoder>(d: &mut D) -> Result<path::PathBuf, D::Error> {
+        use std::ffi::OsString;
         use std::os::unix::prelude::*;
-        let bytes: Vec<u8> = try!(Decodable::decode(d));
-        let s: OsString = OsStringExt::from_vec(bytes);
-        let mut p = path::PathBuf::new();
-        p.push(s);
-        Ok(p)
+        match try!(d.read_str_or_seq::<u8>()) {
+            StrOrSeq::Str(s) => Ok(path::PathBuf::from(s)),
+            StrOrSeq::Seq(bytes) => Ok(path::PathBuf::from(OsString::from_vec(bytes))),
+        }
     }
     #[cfg(windows)]
     fn decode<D: Decoder>(d: &mut D) -> Result<path::PathBuf, D::Error> {
+        use std::ffi::OsString;
         use std::os::windows::prelude::*;
-        let bytes: Vec<u16> = try!(Decodable::decode(d));
-        let s: OsString = OsStringExt::from_wide(&bytes);
-        let mut p = path::PathBuf::new();
-        p.push(s);
-        Ok(p)
+        match try!(d.read_str_or_seq::<u16>()) {
+            StrOrSeq::Str(s) => Ok(path::PathBuf::from(s)),
+            StrOrSeq::Seq(units) => Ok(path::PathBuf::from(OsString::from_wide(&units))),
+        }
     }
+    #[cfg(not(any(unix, windows)))]
+    fn decode<D: Decoder>(d: &mut D) -> Result<path::PathBuf, D::Error> {
+        let s: String = try!(Decodable::decode(d));
+        Ok(path::PathBuf::from(s))
+    }
+}
+
+macro_rules! net_addr_impls {
+    ($($ty:ident => $err:expr,)*) => {$(
+        impl Encodable for net::$ty {
+            fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+                s.emit_str(&self.to_string())
+            }
+        }
+
+        impl Decodable for net::$ty {
+            fn decode<D: Decoder>(d: &mut D) -> Result<net::$ty, D::Error> {
+                let s = try!(d.read_str());
+                s.parse().map_err(|_| d.error($err))
+            }
+        }
+    )*}
+}
+
+// Addresses are encoded in their usual string forms, such as "127.0.0.1",
+// "::1" and "[::1]:8080".
+net_addr_impls! {
+    IpAddr => "invalid IP address",
+    Ipv4Addr => "invalid IPv4 address",
+    Ipv6Addr => "invalid IPv6 address",
+    SocketAddr => "invalid socket address",
+    SocketAddrV4 => "invalid IPv4 socket address",
+    SocketAddrV6 => "invalid IPv6 socket address",
 }
 
 impl<T: Encodable + Copy> Encodable for Cell<T> {
@@ -1437,6 +1682,47 @@ impl<T: Decodable> Decodable for RefCell<T> {
     }
 }
 
+/// Locks the mutex for the duration of the encode, blocking until it is
+/// available. If the mutex is poisoned, the encoder's `error` is returned, or
+/// the value is encoded regardless when the encoder has no such error.
+impl<T: Encodable> Encodable for Mutex<T> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        match self.lock() {
+            Ok(guard) => guard.encode(s),
+            Err(poisoned) => match s.error("mutex is poisoned") {
+                Some(e) => Err(e),
+                None => poisoned.into_inner().encode(s),
+            },
+        }
+    }
+}
+
+impl<T: Decodable> Decodable for Mutex<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Mutex<T>, D::Error> {
+        Ok(Mutex::new(try!(Decodable::decode(d))))
+    }
+}
+
+/// Takes a read lock for the duration of the encode, blocking while a writer
+/// holds the lock. Poisoning is handled as for `Mutex`.
+impl<T: Encodable> Encodable for RwLock<T> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        match self.read() {
+            Ok(guard) => guard.encode(s),
+            Err(poisoned) => match s.error("rwlock is poisoned") {
+                Some(e) => Err(e),
+                None => poisoned.into_inner().encode(s),
+            },
+        }
+    }
+}
+
+impl<T: Decodable> Decodable for RwLock<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<RwLock<T>, D::Error> {
+        Ok(RwLock::new(try!(Decodable::decode(d))))
+    }
+}
+
 impl<T:Encodable> Encodable for Arc<T> {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
         (**self).encode(s)